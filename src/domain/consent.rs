@@ -0,0 +1,80 @@
+use serde::Serialize;
+use sqlx::{FromRow, Row, postgres::PgRow};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+
+/// One published version of a legal document (`terms`, `privacy`, ...).
+/// The newest published version of each kind is the current one.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyDocument {
+    pub id: Uuid,
+    pub kind: String,
+    pub version: String,
+    pub url: String,
+    /// Users must accept this version before they can sign in again.
+    pub mandatory: bool,
+    #[serde(with = "time::serde::timestamp")]
+    pub published_at: OffsetDateTime,
+}
+
+impl FromRow<'_, PgRow> for PolicyDocument {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            kind: row.try_get("kind")?,
+            version: row.try_get("version")?,
+            url: row.try_get("url")?,
+            mandatory: row.try_get("mandatory")?,
+            published_at: row.try_get("published_at")?,
+        })
+    }
+}
+
+const CURRENT_DOCUMENTS: &str =
+    "SELECT DISTINCT ON (kind) id, kind, version, url, mandatory, published_at
+     FROM policy_documents
+     WHERE published_at <= now()
+     ORDER BY kind, published_at DESC";
+
+pub async fn current(db: &Db) -> Result<Vec<PolicyDocument>, sqlx::Error> {
+    sqlx::query_as(CURRENT_DOCUMENTS).fetch_all(db).await
+}
+
+/// Current mandatory documents the user has not accepted yet.
+pub async fn pending(db: &Db, user_id: Uuid) -> Result<Vec<PolicyDocument>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "WITH current AS ({CURRENT_DOCUMENTS})
+         SELECT * FROM current c
+         WHERE c.mandatory
+           AND NOT EXISTS (SELECT 1 FROM user_consents uc WHERE uc.user_id = $1 AND uc.document_id = c.id)"
+    ))
+    .bind(user_id)
+    .fetch_all(db)
+    .await
+}
+
+/// Records acceptance of the given documents; unknown ids and repeat
+/// acceptances are ignored.
+pub async fn record(
+    db: &Db,
+    user_id: Uuid,
+    document_ids: &[Uuid],
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO user_consents (id, user_id, document_id, accepted_at, ip, user_agent)
+         SELECT gen_random_uuid(), $1, d.id, now(), $3, $4
+         FROM policy_documents d WHERE d.id = ANY($2)
+         ON CONFLICT (user_id, document_id) DO NOTHING",
+    )
+    .bind(user_id)
+    .bind(document_ids)
+    .bind(ip)
+    .bind(user_agent)
+    .execute(db)
+    .await?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
+pub mod consent;
 pub mod mfa;
+pub mod profile;
 pub mod session;
 pub mod token;
 pub mod user;
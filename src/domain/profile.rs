@@ -0,0 +1,97 @@
+//! Validation rules for user-editable profile fields.
+
+pub const MAX_NAME_CHARS: usize = 100;
+pub const MAX_METADATA_BYTES: usize = 8 * 1024;
+pub const MAX_METADATA_KEYS: usize = 50;
+pub const MAX_METADATA_KEY_CHARS: usize = 64;
+pub const MAX_METADATA_DEPTH: usize = 4;
+
+pub fn validate_name(name: &str) -> Result<(), &'static str> {
+    let count = name.chars().count();
+    if count == 0 || count > MAX_NAME_CHARS {
+        return Err("name must be 1-100 characters");
+    }
+    if name.chars().any(char::is_control) {
+        return Err("name contains control characters");
+    }
+    Ok(())
+}
+
+/// BCP 47 shape check (`en`, `pt-BR`, `zh-Hant-TW`); not a registry lookup.
+pub fn validate_locale(locale: &str) -> Result<(), &'static str> {
+    let mut parts = locale.split('-');
+    let primary = parts.next().unwrap_or_default();
+    let primary_ok =
+        (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic());
+    let rest_ok =
+        parts.all(|p| (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()));
+    if locale.len() <= 35 && primary_ok && rest_ok {
+        Ok(())
+    } else {
+        Err("locale must be a BCP 47 language tag")
+    }
+}
+
+/// IANA zone name shape (`UTC`, `Europe/Paris`, `America/Argentina/Salta`).
+pub fn validate_timezone(tz: &str) -> Result<(), &'static str> {
+    if tz == "UTC" || tz == "GMT" {
+        return Ok(());
+    }
+    let well_formed = tz.len() <= 64
+        && tz.contains('/')
+        && tz.split('/').all(|seg| {
+            !seg.is_empty()
+                && seg != ".."
+                && seg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        });
+    if well_formed {
+        Ok(())
+    } else {
+        Err("timezone must be an IANA zone name")
+    }
+}
+
+pub fn validate_avatar_url(url: &str) -> Result<(), &'static str> {
+    let Some(rest) = url.strip_prefix("https://") else {
+        return Err("avatar_url must be an https URL");
+    };
+    if url.len() > 2048
+        || rest.is_empty()
+        || url.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err("avatar_url is malformed");
+    }
+    Ok(())
+}
+
+pub fn validate_metadata(value: &serde_json::Value) -> Result<(), &'static str> {
+    let Some(obj) = value.as_object() else {
+        return Err("metadata must be a JSON object");
+    };
+    if obj.len() > MAX_METADATA_KEYS {
+        return Err("metadata has too many keys");
+    }
+    if obj
+        .keys()
+        .any(|k| k.is_empty() || k.chars().count() > MAX_METADATA_KEY_CHARS)
+    {
+        return Err("metadata keys must be 1-64 characters");
+    }
+    if depth(value) > MAX_METADATA_DEPTH {
+        return Err("metadata is nested too deeply");
+    }
+    if value.to_string().len() > MAX_METADATA_BYTES {
+        return Err("metadata is too large");
+    }
+    Ok(())
+}
+
+fn depth(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::Object(map) => 1 + map.values().map(depth).max().unwrap_or(0),
+        serde_json::Value::Array(items) => 1 + items.iter().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
@@ -9,6 +9,9 @@ pub struct Session {
     pub device_id: Option<String>,
     pub user_agent: Option<String>,
     pub ip: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<i64>,
     pub created_at: OffsetDateTime,
     pub last_seen_at: OffsetDateTime,
     pub mfa_passed: bool,
@@ -13,4 +13,7 @@ pub struct RefreshToken {
     pub user_agent: Option<String>,
     pub ip: Option<String>,
     pub rotated_from: Option<Uuid>,
+    pub session_started_at: OffsetDateTime,
+    pub auth_time: OffsetDateTime,
+    pub amr: Vec<String>,
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Row, postgres::PgRow};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
@@ -6,10 +7,36 @@ use uuid::Uuid;
 pub struct User {
     pub id: Uuid,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password_hash: String,
+    /// Display name.
     pub name: Option<String>,
     pub role: String,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Free-form client data, validated on write.
+    pub metadata: Option<serde_json::Value>,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
     pub banned: bool,
 }
+
+impl FromRow<'_, PgRow> for User {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            email: row.try_get("email")?,
+            password_hash: row.try_get("password_hash")?,
+            name: row.try_get("name")?,
+            role: row.try_get("role")?,
+            locale: row.try_get("locale")?,
+            timezone: row.try_get("timezone")?,
+            avatar_url: row.try_get("avatar_url")?,
+            metadata: row.try_get("metadata")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            banned: row.try_get("banned")?,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use maxminddb::{Reader, geoip2};
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Where an address is, as far as the local MaxMind databases know.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 code.
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<i64>,
+    pub asn_org: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Offline GeoIP lookups from MaxMind-format files (`GEOIP_CITY_DB` for a
+/// GeoLite2/GeoIP2 City database, `GEOIP_ASN_DB` for an ASN one). Either may
+/// be absent, in which case the corresponding fields stay empty.
+#[derive(Clone, Default)]
+pub struct GeoIp {
+    city: Option<Arc<Reader<Vec<u8>>>>,
+    asn: Option<Arc<Reader<Vec<u8>>>>,
+}
+
+impl GeoIp {
+    pub fn from_env() -> Self {
+        Self {
+            city: open("GEOIP_CITY_DB"),
+            asn: open("GEOIP_ASN_DB"),
+        }
+    }
+
+    /// `None` when the address is unparsable or no database knows it.
+    pub fn lookup(&self, ip: &str) -> Option<GeoInfo> {
+        let addr: IpAddr = ip.parse().ok()?;
+        let mut info = GeoInfo::default();
+        let mut found = false;
+
+        if let Some(city) = self
+            .city
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::City>(addr).ok())
+        {
+            found = true;
+            info.country = city.country.and_then(|c| c.iso_code).map(|s| s.to_string());
+            info.city = city
+                .city
+                .and_then(|c| c.names)
+                .and_then(|names| names.get("en").map(|s| s.to_string()));
+            if let Some(location) = city.location {
+                info.latitude = location.latitude;
+                info.longitude = location.longitude;
+            }
+        }
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|r| r.lookup::<geoip2::Asn>(addr).ok())
+        {
+            found = true;
+            info.asn = asn.autonomous_system_number.map(i64::from);
+            info.asn_org = asn.autonomous_system_organization.map(|s| s.to_string());
+        }
+        found.then_some(info)
+    }
+}
+
+fn open(var: &str) -> Option<Arc<Reader<Vec<u8>>>> {
+    let path = std::env::var(var).ok().filter(|v| !v.trim().is_empty())?;
+    match Reader::open_readfile(&path) {
+        Ok(reader) => {
+            info!("loaded GeoIP database {path}");
+            Some(Arc::new(reader))
+        }
+        Err(e) => {
+            warn!("failed to open {var}={path}: {e}; GeoIP lookups disabled");
+            None
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use reqwest::Client;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Outgoing transactional email. Delivery is delegated to an HTTP relay when
+/// `MAIL_WEBHOOK_URL` is set; otherwise messages are only logged, which is
+/// enough for local development.
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    app_url: String,
+    webhook_url: Option<String>,
+    http: Client,
+}
+
+#[derive(Serialize)]
+struct OutgoingMail<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    text: &'a str,
+}
+
+impl Mailer {
+    pub fn from_env() -> Self {
+        let from = std::env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@tajawal.local".into());
+        let app_url = std::env::var("APP_BASE_URL")
+            .unwrap_or_else(|_| "http://localhost:3000".into())
+            .trim_end_matches('/')
+            .to_string();
+        let webhook_url = std::env::var("MAIL_WEBHOOK_URL")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        Self {
+            from,
+            app_url,
+            webhook_url,
+            http: Client::new(),
+        }
+    }
+
+    /// Absolute link into the frontend, e.g. `link("/reset?token=..")`.
+    pub fn link(&self, path_and_query: &str) -> String {
+        format!("{}{}", self.app_url, path_and_query)
+    }
+
+    /// Best effort: failures are logged, never surfaced to the caller, so a
+    /// mail outage cannot block security-relevant state changes.
+    pub async fn send(&self, to: &str, subject: &str, text: &str) {
+        let Some(url) = &self.webhook_url else {
+            info!("mail to {to}: {subject}\n{text}");
+            return;
+        };
+        let res = self
+            .http
+            .post(url)
+            .json(&OutgoingMail {
+                from: &self.from,
+                to,
+                subject,
+                text,
+            })
+            .send()
+            .await;
+        match res {
+            Ok(r) if r.status().is_success() => {}
+            Ok(r) => warn!("mail relay rejected message to {to}: {}", r.status()),
+            Err(e) => warn!("mail relay unreachable: {e}"),
+        }
+    }
+}
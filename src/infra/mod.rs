@@ -1,2 +1,4 @@
 pub mod db;
+pub mod geoip;
+pub mod mailer;
 pub mod supabase;
@@ -0,0 +1,122 @@
+//! Erases accounts whose deletion grace period has run out.
+//!
+//! The `users` row is kept as an anonymized tombstone so foreign keys and
+//! aggregate security history stay intact; everything that identifies the
+//! person or could authenticate as them is deleted or scrubbed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::security::stuffing;
+use crate::state::AppState;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BATCH_SIZE: i64 = 100;
+
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match purge_due(&state).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("purged {n} deleted accounts"),
+                Err(e) => tracing::error!("account purge failed: {e}"),
+            }
+        }
+    });
+}
+
+async fn purge_due(state: &AppState) -> Result<usize, sqlx::Error> {
+    let due: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM users WHERE deletion_scheduled_for <= now() ORDER BY deletion_scheduled_for LIMIT $1",
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await?;
+
+    for user_id in &due {
+        purge_user(state, *user_id).await?;
+    }
+    Ok(due.len())
+}
+
+async fn purge_user(state: &AppState, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+
+    let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM identifier_lockouts WHERE identifier_hash = $1")
+        .bind(stuffing::identifier_hash(
+            &state.security.identifier_hash_key,
+            &email,
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+    for table in [
+        "refresh_tokens",
+        "bff_sessions",
+        "mfa_totp",
+        "passkeys",
+        "password_resets",
+        "account_unlocks",
+        "email_changes",
+        "data_exports",
+        "login_challenges",
+        "known_devices",
+        "sessions",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE user_id = $1"))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    // Consent records are kept as proof of what was agreed to.
+    for table in ["login_logs", "user_consents"] {
+        sqlx::query(&format!(
+            "UPDATE {table} SET ip = NULL, user_agent = NULL WHERE user_id = $1"
+        ))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query(
+        "UPDATE login_logs SET identifier_hash = NULL, network = NULL, country = NULL, city = NULL,
+                asn = NULL, latitude = NULL, longitude = NULL
+         WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE security_events SET ip = NULL, user_agent = NULL, details = '{}'::jsonb WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    // Scores and outcomes stay for tuning; signal details can carry the
+    // address, network and location.
+    sqlx::query(
+        "UPDATE risk_decisions SET ip = NULL, user_agent = NULL, signals = '[]'::jsonb WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE users SET email = $1, password_hash = '', name = NULL, locale = NULL, timezone = NULL,
+                avatar_url = NULL, metadata = NULL, banned = true, deleted_at = now(),
+                deletion_requested_at = NULL, deletion_scheduled_for = NULL, updated_at = now()
+         WHERE id = $2",
+    )
+    .bind(format!("deleted+{user_id}@invalid"))
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
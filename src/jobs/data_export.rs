@@ -0,0 +1,302 @@
+//! Data-subject access exports: a JSON archive of everything we hold about a
+//! user, built in the background and handed out through an expiring link.
+
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+use sqlx::Row;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::routes::auth::{generate_refresh_token, hash_refresh_token};
+use crate::security::events;
+use crate::state::AppState;
+
+/// How long the emailed download link stays valid.
+pub const LINK_TTL_HOURS: i64 = 48;
+const LOGIN_HISTORY_LIMIT: i64 = 1000;
+/// Longest an archive may take to build before it is given up on.
+const BUILD_TIMEOUT: Duration = Duration::minutes(30);
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Queues an export of `user_id`'s data and returns its id. `requested_by`
+/// differs from `user_id` when support staff trigger it. Returns `None` when
+/// an export for the user is already being built; the partial unique index
+/// on `data_exports (user_id) WHERE status = 'pending'` settles concurrent
+/// requests.
+pub async fn start(
+    state: Arc<AppState>,
+    user_id: Uuid,
+    requested_by: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let id: Option<Uuid> = sqlx::query_scalar(
+        "INSERT INTO data_exports (id, user_id, requested_by, status, created_at)
+         VALUES ($1, $2, $3, 'pending', now())
+         ON CONFLICT (user_id) WHERE status = 'pending' DO NOTHING
+         RETURNING id",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(requested_by)
+    .fetch_optional(&state.db)
+    .await?;
+    let Some(id) = id else {
+        return Ok(None);
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = complete(&state, id, user_id).await {
+            tracing::error!("data export {id} failed: {e}");
+            let _ = sqlx::query("UPDATE data_exports SET status = 'failed' WHERE id = $1")
+                .bind(id)
+                .execute(&state.db)
+                .await;
+        }
+    });
+    Ok(Some(id))
+}
+
+async fn complete(state: &AppState, id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let archive = build(&state.db, user_id).await?;
+    let (raw, hash) = generate_refresh_token();
+    let expires_at = OffsetDateTime::now_utc() + Duration::hours(LINK_TTL_HOURS);
+    let email: String = sqlx::query_scalar(
+        "UPDATE data_exports SET status = 'ready', payload = $1, download_token_hash = $2, expires_at = $3, completed_at = now()
+         FROM users u
+         WHERE data_exports.id = $4 AND u.id = data_exports.user_id
+         RETURNING u.email",
+    )
+    .bind(&archive)
+    .bind(&hash)
+    .bind(expires_at)
+    .bind(id)
+    .fetch_one(&state.db)
+    .await?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "data_export_ready",
+        None,
+        None,
+        json!({ "export_id": id }),
+    )
+    .await;
+    state
+        .mailer
+        .send(
+            &email,
+            "Your data export is ready",
+            &format!(
+                "A copy of your account data is ready to download. The link expires in {LINK_TTL_HOURS} hours:\n\n{}\n\nIf you didn't ask for this, contact support.",
+                state.mailer.link(&format!("/exports/download?token={raw}")),
+            ),
+        )
+        .await;
+    Ok(())
+}
+
+/// Looks up a ready, unexpired export by its download token.
+pub async fn fetch(db: &Db, raw_token: &str) -> Result<Option<Value>, sqlx::Error> {
+    sqlx::query_scalar(
+        "UPDATE data_exports SET downloaded_at = coalesce(downloaded_at, now())
+         WHERE download_token_hash = $1 AND status = 'ready' AND expires_at > now()
+         RETURNING payload",
+    )
+    .bind(hash_refresh_token(raw_token))
+    .fetch_optional(db)
+    .await
+}
+
+/// Drops archives whose link has expired; the row stays as an audit record.
+/// Also fails builds that have been pending for longer than
+/// [`BUILD_TIMEOUT`], which means the instance building them went away:
+/// left pending, they would block the user's next request for good.
+pub fn spawn_cleanup(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sqlx::query(
+                "UPDATE data_exports SET status = 'expired', payload = NULL, download_token_hash = NULL
+                 WHERE status = 'ready' AND expires_at <= now()",
+            )
+            .execute(&state.db)
+            .await
+            {
+                tracing::error!("data export cleanup failed: {e}");
+            }
+            match sqlx::query(
+                "UPDATE data_exports SET status = 'failed'
+                 WHERE status = 'pending' AND created_at <= $1",
+            )
+            .bind(OffsetDateTime::now_utc() - BUILD_TIMEOUT)
+            .execute(&state.db)
+            .await
+            {
+                Ok(res) if res.rows_affected() > 0 => {
+                    tracing::warn!("failed {} stalled data exports", res.rows_affected());
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("data export cleanup failed: {e}"),
+            }
+        }
+    });
+}
+
+async fn build(db: &Db, user_id: Uuid) -> Result<Value, sqlx::Error> {
+    let user = sqlx::query(
+        "SELECT id, email, name, role, locale, timezone, avatar_url, metadata, created_at, updated_at,
+                deletion_scheduled_for
+         FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+    let email: String = user.get("email");
+    let profile = json!({
+        "id": user_id,
+        "email": email,
+        "name": user.get::<Option<String>, _>("name"),
+        "role": user.get::<String, _>("role"),
+        "locale": user.get::<Option<String>, _>("locale"),
+        "timezone": user.get::<Option<String>, _>("timezone"),
+        "avatar_url": user.get::<Option<String>, _>("avatar_url"),
+        "metadata": user.get::<Option<Value>, _>("metadata"),
+        "created_at": timestamp(user.get("created_at")),
+        "updated_at": timestamp(user.get("updated_at")),
+        "deletion_scheduled_for": user
+            .get::<Option<OffsetDateTime>, _>("deletion_scheduled_for")
+            .map(timestamp),
+    });
+
+    let sessions: Vec<Value> = sqlx::query(
+        "SELECT id, created_at, expires_at, revoked_at, user_agent, ip, session_started_at
+         FROM refresh_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "id": r.get::<Uuid, _>("id"),
+            "created_at": timestamp(r.get("created_at")),
+            "expires_at": timestamp(r.get("expires_at")),
+            "revoked_at": r.get::<Option<OffsetDateTime>, _>("revoked_at").map(timestamp),
+            "session_started_at": r
+                .get::<Option<OffsetDateTime>, _>("session_started_at")
+                .map(timestamp),
+            "user_agent": r.get::<Option<String>, _>("user_agent"),
+            "ip": r.get::<Option<String>, _>("ip"),
+        })
+    })
+    .collect();
+
+    let devices: Vec<Value> = sqlx::query(
+        "SELECT user_agent, network, first_seen_at, last_seen_at FROM known_devices
+         WHERE user_id = $1 ORDER BY last_seen_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "user_agent": r.get::<String, _>("user_agent"),
+            "network": r.get::<String, _>("network"),
+            "first_seen_at": timestamp(r.get("first_seen_at")),
+            "last_seen_at": timestamp(r.get("last_seen_at")),
+        })
+    })
+    .collect();
+
+    let login_history: Vec<Value> = sqlx::query(
+        "SELECT created_at, success, failure_reason, ip, user_agent, country, city, asn, latitude, longitude
+         FROM login_logs WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(LOGIN_HISTORY_LIMIT)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "at": timestamp(r.get("created_at")),
+            "success": r.get::<bool, _>("success"),
+            "failure_reason": r.get::<Option<String>, _>("failure_reason"),
+            "ip": r.get::<Option<String>, _>("ip"),
+            "user_agent": r.get::<Option<String>, _>("user_agent"),
+            "country": r.get::<Option<String>, _>("country"),
+            "city": r.get::<Option<String>, _>("city"),
+            "asn": r.get::<Option<i64>, _>("asn"),
+            "latitude": r.get::<Option<f64>, _>("latitude"),
+            "longitude": r.get::<Option<f64>, _>("longitude"),
+        })
+    })
+    .collect();
+
+    let totp = sqlx::query("SELECT enabled, created_at FROM mfa_totp WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+    let passkeys: Vec<Value> = sqlx::query(
+        "SELECT name, created_at, last_used_at FROM passkeys WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "name": r.get::<Option<String>, _>("name"),
+            "created_at": timestamp(r.get("created_at")),
+            "last_used_at": r.get::<Option<OffsetDateTime>, _>("last_used_at").map(timestamp),
+        })
+    })
+    .collect();
+    let mfa = json!({
+        "totp_enabled": totp.as_ref().is_some_and(|r| r.get::<bool, _>("enabled")),
+        "totp_enrolled_at": totp.map(|r| timestamp(r.get("created_at"))),
+        "passkeys": passkeys,
+    });
+
+    let consents: Vec<Value> = sqlx::query(
+        "SELECT d.kind, d.version, d.url, c.accepted_at, c.ip
+         FROM user_consents c JOIN policy_documents d ON d.id = c.document_id
+         WHERE c.user_id = $1 ORDER BY c.accepted_at",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "kind": r.get::<String, _>("kind"),
+            "version": r.get::<String, _>("version"),
+            "url": r.get::<String, _>("url"),
+            "accepted_at": timestamp(r.get("accepted_at")),
+            "ip": r.get::<Option<String>, _>("ip"),
+        })
+    })
+    .collect();
+
+    // Email/password is the only sign-in identity this service links.
+    let identities = json!([{ "provider": "email", "identifier": email }]);
+
+    Ok(json!({
+        "generated_at": timestamp(OffsetDateTime::now_utc()),
+        "profile": profile,
+        "sessions": sessions,
+        "devices": devices,
+        "login_history": login_history,
+        "mfa": mfa,
+        "identities": identities,
+        "consents": consents,
+    }))
+}
+
+fn timestamp(at: OffsetDateTime) -> i64 {
+    at.unix_timestamp()
+}
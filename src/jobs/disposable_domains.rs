@@ -0,0 +1,27 @@
+//! Reloads the disposable email domain list from disk on the configured
+//! schedule.
+
+use std::sync::Arc;
+
+use crate::security::email_policy;
+use crate::state::AppState;
+
+pub fn spawn(state: Arc<AppState>) {
+    let Some(path) = state.security.disposable_domains_file.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut ticker =
+            tokio::time::interval(state.security.disposable_domains_refresh.unsigned_abs());
+        loop {
+            ticker.tick().await;
+            match email_policy::reload_disposable(&path).await {
+                Ok(n) => tracing::info!("loaded {n} disposable email domains"),
+                Err(e) => tracing::error!(
+                    "disposable domain list {} reload failed: {e}",
+                    path.display()
+                ),
+            }
+        }
+    });
+}
@@ -0,0 +1,22 @@
+//! Keeps the in-memory IP rule index in step with `ip_rules`, so bans made by
+//! another instance, and expiries, take effect here within a minute.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::security::ip_rules;
+use crate::state::AppState;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = ip_rules::reload(&state.db).await {
+                tracing::error!("ip rule refresh failed: {e}");
+            }
+        }
+    });
+}
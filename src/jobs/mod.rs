@@ -0,0 +1,5 @@
+pub mod account_purge;
+pub mod data_export;
+pub mod disposable_domains;
+pub mod ip_rules;
+pub mod reputation;
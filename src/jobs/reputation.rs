@@ -0,0 +1,22 @@
+//! Reloads the IP reputation feeds from disk on the configured schedule.
+
+use std::sync::Arc;
+
+use crate::security::reputation;
+use crate::state::AppState;
+
+pub fn spawn(state: Arc<AppState>) {
+    if state.security.reputation_feeds.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(state.security.reputation_refresh.unsigned_abs());
+        loop {
+            ticker.tick().await;
+            match reputation::reload(&state.security.reputation_feeds).await {
+                Ok(n) => tracing::info!("loaded {n} reputation feed entries"),
+                Err(e) => tracing::error!("reputation feed reload failed: {e}"),
+            }
+        }
+    });
+}
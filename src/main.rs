@@ -1,14 +1,18 @@
 mod domain;
 mod infra;
+mod jobs;
 mod middleware;
 mod routes;
 mod security;
 mod state;
 
-use axum::{Router, routing::get};
+use axum::{Extension, Router, middleware::from_fn, routing::get};
 use infra::db::connect;
+use infra::geoip::GeoIp;
+use infra::mailer::Mailer;
 use infra::supabase::SupabaseCtx;
 use security::config::SecurityConfig;
+use security::risk::RiskEngine;
 use std::net::SocketAddr;
 use tower_http::cors::AllowHeaders;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
@@ -24,35 +28,46 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    security::password::prepare_dummy_hash();
     let db = connect().await?;
     let jwt = security::jwt::JwtManager::default();
     let security = SecurityConfig::default();
     let supabase = SupabaseCtx::from_env()?;
-    let shared_state = state::AppState::new(db, jwt, security, supabase);
-    let cors = build_cors();
+    let mailer = Mailer::from_env();
+    let risk = RiskEngine::with_default_signals(&security);
+    let cors = build_cors(&security.allowed_origins);
+    let geoip = GeoIp::from_env();
+    let shared_state = state::AppState::new(db, jwt, security, supabase, mailer, risk, geoip);
+
+    jobs::account_purge::spawn(shared_state.clone());
+    jobs::data_export::spawn_cleanup(shared_state.clone());
+    jobs::disposable_domains::spawn(shared_state.clone());
+    jobs::ip_rules::spawn(shared_state.clone());
+    jobs::reputation::spawn(shared_state.clone());
 
     let app = Router::new()
         .merge(routes::router())
         .route("/health", get(|| async { "OK" }))
+        .layer(from_fn(middleware::client_ip::resolve_client_ip))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
+        .layer(Extension(shared_state.clone()))
         .with_state(shared_state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
     let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
 
-fn build_cors() -> CorsLayer {
-    let origins = std::env::var("ALLOWED_ORIGINS").unwrap_or_default();
+fn build_cors(origins: &[String]) -> CorsLayer {
     let mut allowed = Vec::new();
-    for origin in origins
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-    {
+    for origin in origins {
         if let Ok(val) = origin.parse() {
             allowed.push(val);
         } else {
@@ -68,6 +83,8 @@ fn build_cors() -> CorsLayer {
             .allow_methods(vec![
                 http::Method::GET,
                 http::Method::POST,
+                http::Method::PATCH,
+                http::Method::DELETE,
                 http::Method::OPTIONS,
             ])
             .allow_headers(AllowHeaders::any())
@@ -7,32 +7,36 @@ use std::sync::Arc;
 pub async fn admin_only(
     req: axum::http::Request<axum::body::Body>,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, (StatusCode, String)> {
     let state = req
         .extensions()
         .get::<Arc<AppState>>()
         .cloned()
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "missing state".into()))?;
     let claims = req.extensions().get::<Claims>().cloned();
     let Some(c) = claims else {
-        return Err(StatusCode::UNAUTHORIZED);
+        return Err((StatusCode::UNAUTHORIZED, "unauthorized".into()));
     };
 
     let row = sqlx::query("SELECT role FROM users WHERE id = $1")
         .bind(&c.sub)
         .fetch_optional(&state.db)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "db error".into()))?;
 
     match row {
         Some(r) => {
             let role: String = r.get("role");
-            if role == "admin" {
-                Ok(next.run(req).await)
-            } else {
-                Err(StatusCode::FORBIDDEN)
+            if role != "admin" {
+                return Err((StatusCode::FORBIDDEN, "forbidden".into()));
             }
+            // Checked against the stored role, not the token's, so a stale
+            // role claim cannot skip the MFA requirement.
+            if state.security.role_requires_mfa(&role) && !c.mfa_passed() {
+                return Err((StatusCode::FORBIDDEN, "mfa_required".into()));
+            }
+            Ok(next.run(req).await)
         }
-        None => Err(StatusCode::UNAUTHORIZED),
+        None => Err((StatusCode::UNAUTHORIZED, "unauthorized".into())),
     }
 }
@@ -1,7 +1,10 @@
 use axum::{http::StatusCode, middleware::Next, response::Response};
 use std::sync::Arc;
 
-use crate::{security::jwt::JwtManager, state::AppState};
+use crate::{
+    security::{bff, jwt::JwtManager},
+    state::AppState,
+};
 use cookie::Cookie;
 
 pub async fn auth_middleware(
@@ -29,10 +32,25 @@ pub async fn auth_middleware(
         }
     }
 
+    if state.security.bff_mode
+        && let Some(session) = cookie_token(req.headers(), &state.security.bff_cookie_name)
+    {
+        let claims = bff::resolve(&state.db, jwt, &state.security, &session)
+            .await
+            .map_err(|e| {
+                tracing::error!("bff session lookup failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        if let Some(claims) = claims {
+            req.extensions_mut().insert(claims);
+            return Ok(next.run(req).await);
+        }
+    }
+
     Err(StatusCode::UNAUTHORIZED)
 }
 
-fn bearer_from_header(headers: &axum::http::HeaderMap) -> Option<String> {
+pub fn bearer_from_header(headers: &axum::http::HeaderMap) -> Option<String> {
     headers
         .get(axum::http::header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
@@ -40,7 +58,7 @@ fn bearer_from_header(headers: &axum::http::HeaderMap) -> Option<String> {
         .map(|s| s.to_string())
 }
 
-fn cookie_token(headers: &axum::http::HeaderMap, name: &str) -> Option<String> {
+pub fn cookie_token(headers: &axum::http::HeaderMap, name: &str) -> Option<String> {
     let cookie_header = headers.get(axum::http::header::COOKIE)?.to_str().ok()?;
     for part in cookie_header.split(';') {
         if let Ok(parsed) = Cookie::parse(part.trim().to_string()) {
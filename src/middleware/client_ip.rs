@@ -0,0 +1,13 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+
+use crate::security::client_ip::ClientIp;
+
+/// Resolves the client address once per request so rate limiting, risk checks
+/// and audit records all see the same, proxy-aware value.
+pub async fn resolve_client_ip(req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    if let Some(ip) = ClientIp::from_parts(&parts) {
+        parts.extensions.insert(ip);
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
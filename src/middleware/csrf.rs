@@ -0,0 +1,76 @@
+use axum::{
+    http::{Method, Request, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::middleware::auth::{bearer_from_header, cookie_token};
+use crate::security::csrf;
+use crate::state::AppState;
+
+/// Sign-in entry points: they act on credentials in the body, not on the
+/// session cookies, and a client that lost its CSRF token must still reach
+/// them. The Origin check still applies.
+const TOKEN_EXEMPT_PATHS: &[&str] = &["/auth/login", "/auth/register"];
+
+/// Enforces the double-submit CSRF token and Origin/Referer allow-list on
+/// state-changing requests that rely on ambient cookies. Requests
+/// authenticated by a valid bearer token are exempt: browsers never attach
+/// one cross-site on their own. An invalid one does not count, since
+/// `auth_middleware` would then fall back to the cookies.
+pub async fn csrf_protect(
+    req: Request<axum::body::Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    if matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) {
+        return Ok(next.run(req).await);
+    }
+
+    let state = req
+        .extensions()
+        .get::<Arc<AppState>>()
+        .cloned()
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "missing state".into()))?;
+    let cfg = &state.security;
+    let headers = req.headers();
+
+    if bearer_from_header(headers).is_some_and(|token| state.jwt.verify(&token).is_ok()) {
+        return Ok(next.run(req).await);
+    }
+    let cookie_authenticated = cookie_token(headers, &cfg.access_cookie_name).is_some()
+        || cookie_token(headers, &cfg.refresh_cookie_name).is_some()
+        || (cfg.bff_mode && cookie_token(headers, &cfg.bff_cookie_name).is_some());
+    if !cookie_authenticated {
+        return Ok(next.run(req).await);
+    }
+
+    if !cfg.allowed_origins.is_empty() {
+        let source = headers
+            .get(header::ORIGIN)
+            .or_else(|| headers.get(header::REFERER))
+            .and_then(|v| v.to_str().ok());
+        if let Some(source) = source
+            && !csrf::origin_allowed(source, &cfg.allowed_origins)
+        {
+            return Err((StatusCode::FORBIDDEN, "csrf_origin_mismatch".into()));
+        }
+    }
+
+    if TOKEN_EXEMPT_PATHS.contains(&req.uri().path()) {
+        return Ok(next.run(req).await);
+    }
+    let expected = cookie_token(headers, &cfg.csrf_cookie_name).unwrap_or_default();
+    let presented = headers
+        .get(csrf::CSRF_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !csrf::tokens_match(&expected, presented) {
+        return Err((StatusCode::FORBIDDEN, "csrf_token_invalid".into()));
+    }
+
+    Ok(next.run(req).await)
+}
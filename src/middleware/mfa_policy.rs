@@ -0,0 +1,28 @@
+use axum::{http::StatusCode, middleware::Next, response::Response};
+use std::sync::Arc;
+
+use crate::security::jwt::Claims;
+use crate::state::AppState;
+
+/// Blocks sessions of roles listed in `MFA_REQUIRED_ROLES` until they have
+/// passed MFA. Enrollment and reauthentication routes are deliberately not
+/// behind this layer so such users can satisfy it.
+pub async fn require_mfa_for_role(
+    req: axum::http::Request<axum::body::Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let state = req
+        .extensions()
+        .get::<Arc<AppState>>()
+        .cloned()
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "missing state".into()))?;
+    let Some(claims) = req.extensions().get::<Claims>() else {
+        return Err((StatusCode::UNAUTHORIZED, "unauthorized".into()));
+    };
+
+    let role = claims.role.as_deref().unwrap_or("user");
+    if state.security.role_requires_mfa(role) && !claims.mfa_passed() {
+        return Err((StatusCode::FORBIDDEN, "mfa_required".into()));
+    }
+    Ok(next.run(req).await)
+}
@@ -1,3 +1,7 @@
 pub mod admin;
 pub mod auth;
+pub mod client_ip;
+pub mod csrf;
+pub mod mfa_policy;
 pub mod rate_limit;
+pub mod step_up;
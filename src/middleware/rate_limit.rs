@@ -4,6 +4,7 @@ use once_cell::sync::Lazy;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::security::client_ip::ClientIp;
 use crate::state::AppState;
 
 const MAX_REQUESTS: u32 = 10;
@@ -22,13 +23,10 @@ pub async fn rate_limit_with_config(
 ) -> Result<Response, (StatusCode, String)> {
     let _state = req.extensions().get::<Arc<AppState>>().cloned();
     let path = req.uri().path().to_string();
-    let ip = req
-        .headers()
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or("").trim().to_string())
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| "unknown".into());
+    let ClientIp(ip) = req.extensions().get::<ClientIp>().copied().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "client address unavailable".to_string(),
+    ))?;
     let key = format!("{}:{}", ip, path);
 
     let mut entry = BUCKETS.entry(key).or_insert_with(|| Bucket {
@@ -0,0 +1,74 @@
+use axum::{
+    Json,
+    extract::State,
+    http::{Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use time::Duration;
+
+use crate::security::jwt::Claims;
+use crate::state::AppState;
+
+/// Freshness requirement for a route group. `max_age: None` falls back to
+/// `SecurityConfig::step_up_max_age`.
+#[derive(Clone, Copy, Default)]
+pub struct StepUpPolicy {
+    pub max_age: Option<Duration>,
+    pub require_mfa: bool,
+}
+
+#[derive(Serialize)]
+struct StepUpRequired {
+    error: &'static str,
+    max_age: i64,
+    mfa_required: bool,
+}
+
+/// Rejects requests whose token does not carry a recent enough (and, if
+/// required, multi-factor) authentication. Must run after `auth_middleware`.
+pub async fn require_step_up(
+    State(policy): State<StepUpPolicy>,
+    req: Request<axum::body::Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let state = req
+        .extensions()
+        .get::<Arc<AppState>>()
+        .cloned()
+        .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    let Some(claims) = req.extensions().get::<Claims>() else {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+
+    let max_age = policy.max_age.unwrap_or(state.security.step_up_max_age);
+    let fresh = claims.authenticated_within(max_age);
+    let mfa_ok = !policy.require_mfa || claims.mfa_passed();
+    if fresh && mfa_ok {
+        return Ok(next.run(req).await);
+    }
+
+    // Challenge format from RFC 9470 (OAuth 2.0 Step-Up Authentication).
+    let mut challenge = format!(
+        "Bearer error=\"insufficient_user_authentication\", max_age={}",
+        max_age.whole_seconds()
+    );
+    if policy.require_mfa {
+        challenge.push_str(", acr_values=\"mfa\"");
+    }
+    let mut res = (
+        StatusCode::UNAUTHORIZED,
+        Json(StepUpRequired {
+            error: "step_up_required",
+            max_age: max_age.whole_seconds(),
+            mfa_required: policy.require_mfa,
+        }),
+    )
+        .into_response();
+    if let Ok(value) = challenge.parse() {
+        res.headers_mut().insert(header::WWW_AUTHENTICATE, value);
+    }
+    Err(res)
+}
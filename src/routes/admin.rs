@@ -1,6 +1,18 @@
+use crate::jobs::data_export;
+use crate::security::email_policy::{
+    self, DomainAction, DomainRule, DomainRuleError, NewDomainRule,
+};
+use crate::security::ip_rules::{self, IpRule, IpRuleError, NewIpRule};
+use crate::security::jwt::Claims;
+use crate::security::lockout::{self, IpLockout};
 use crate::state::AppState;
-use axum::{Json, Router, routing::get};
-use serde::Serialize;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{
+    Extension, Json, Router,
+    routing::{delete, get, post},
+};
+use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use std::sync::Arc;
 
@@ -8,6 +20,17 @@ pub fn router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/health", get(health))
         .route("/users", get(list_users))
+        .route("/users/:id/export", post(export_user))
+        .route("/ip-rules", get(list_ip_rules).post(create_ip_rule))
+        .route("/ip-rules/:id", delete(delete_ip_rule))
+        .route("/users/:id/unlock", post(unlock_user))
+        .route("/ip-lockouts", get(list_ip_lockouts))
+        .route("/ip-lockouts/:ip", delete(unlock_ip))
+        .route(
+            "/email-domains",
+            get(list_email_domain_rules).post(create_email_domain_rule),
+        )
+        .route("/email-domains/:id", delete(delete_email_domain_rule))
 }
 
 #[derive(Serialize)]
@@ -49,3 +72,211 @@ async fn list_users(
 
     Ok(Json(data))
 }
+
+/// Support-initiated data export; the link still goes to the user's own inbox.
+async fn export_user(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, String)> {
+    let admin_id = claims
+        .sub
+        .parse()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid subject".to_string()))?;
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, "Unknown user".into()));
+    }
+    let export_id = data_export::start(state, user_id, admin_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::CONFLICT, "export_in_progress".to_string()))?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "export_id": export_id })),
+    ))
+}
+
+fn ip_rule_error(e: IpRuleError) -> (StatusCode, String) {
+    match e {
+        IpRuleError::Db(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        other => (StatusCode::BAD_REQUEST, other.to_string()),
+    }
+}
+
+async fn list_ip_rules(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<IpRule>>, (StatusCode, String)> {
+    ip_rules::list(&state.db)
+        .await
+        .map(Json)
+        .map_err(ip_rule_error)
+}
+
+#[derive(Deserialize)]
+struct CreateIpRulePayload {
+    /// `203.0.113.0/24`, `2001:db8::/32` or a single address.
+    cidr: Option<String>,
+    asn: Option<i64>,
+    reason: String,
+    /// Unix seconds; omit for a permanent rule.
+    #[serde(default, with = "time::serde::timestamp::option")]
+    expires_at: Option<time::OffsetDateTime>,
+}
+
+async fn create_ip_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateIpRulePayload>,
+) -> Result<(StatusCode, Json<IpRule>), (StatusCode, String)> {
+    let admin_id = claims
+        .sub
+        .parse()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid subject".to_string()))?;
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "A reason is required".into()));
+    }
+    let rule = ip_rules::create(
+        &state.db,
+        NewIpRule {
+            cidr: payload.cidr,
+            asn: payload.asn,
+            reason: reason.to_string(),
+            expires_at: payload.expires_at,
+            created_by: Some(admin_id),
+        },
+    )
+    .await
+    .map_err(ip_rule_error)?;
+    tracing::info!(admin = %admin_id, rule = %rule.id, "ip rule added");
+    ip_rules::reload(&state.db).await.map_err(ip_rule_error)?;
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+async fn delete_ip_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if !ip_rules::delete(&state.db, id)
+        .await
+        .map_err(ip_rule_error)?
+    {
+        return Err((StatusCode::NOT_FOUND, "Unknown rule".into()));
+    }
+    tracing::info!(admin = %claims.sub, rule = %id, "ip rule removed");
+    ip_rules::reload(&state.db).await.map_err(ip_rule_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unlock_user(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if !lockout::unlock_user(&state.db, user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    {
+        return Err((StatusCode::NOT_FOUND, "Unknown user".into()));
+    }
+    tracing::info!(admin = %claims.sub, user = %user_id, "account unlocked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_ip_lockouts(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<IpLockout>>, (StatusCode, String)> {
+    lockout::locked_ips(&state.db)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn unlock_ip(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(ip): Path<std::net::IpAddr>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let ip = ip.to_string();
+    if !lockout::unlock_ip(&state.db, &ip)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    {
+        return Err((StatusCode::NOT_FOUND, "No lockout for that address".into()));
+    }
+    tracing::info!(admin = %claims.sub, ip = %ip, "address unlocked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn domain_rule_error(e: DomainRuleError) -> (StatusCode, String) {
+    match e {
+        DomainRuleError::Db(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        other => (StatusCode::BAD_REQUEST, other.to_string()),
+    }
+}
+
+async fn list_email_domain_rules(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<DomainRule>>, (StatusCode, String)> {
+    email_policy::list_rules(&state.db)
+        .await
+        .map(Json)
+        .map_err(domain_rule_error)
+}
+
+#[derive(Deserialize)]
+struct CreateDomainRulePayload {
+    /// `example.com` also covers its subdomains.
+    domain: String,
+    action: DomainAction,
+    reason: String,
+}
+
+async fn create_email_domain_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateDomainRulePayload>,
+) -> Result<(StatusCode, Json<DomainRule>), (StatusCode, String)> {
+    let admin_id = claims
+        .sub
+        .parse()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid subject".to_string()))?;
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "A reason is required".into()));
+    }
+    let rule = email_policy::create_rule(
+        &state.db,
+        NewDomainRule {
+            domain: payload.domain,
+            action: payload.action,
+            reason: reason.to_string(),
+            created_by: Some(admin_id),
+        },
+    )
+    .await
+    .map_err(domain_rule_error)?;
+    tracing::info!(admin = %admin_id, rule = %rule.id, "email domain rule set for {}", rule.domain);
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+async fn delete_email_domain_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if !email_policy::delete_rule(&state.db, id)
+        .await
+        .map_err(domain_rule_error)?
+    {
+        return Err((StatusCode::NOT_FOUND, "Unknown rule".into()));
+    }
+    tracing::info!(admin = %claims.sub, rule = %id, "email domain rule removed");
+    Ok(StatusCode::NO_CONTENT)
+}
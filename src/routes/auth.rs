@@ -1,10 +1,10 @@
 use axum::{
-    Json, Router,
-    extract::State,
-    http::header::SET_COOKIE,
+    Extension, Json, Router,
+    extract::{Path, State},
+    http::header::{RETRY_AFTER, SET_COOKIE},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{delete, get, post},
 };
 use cookie::Cookie;
 use cookie::time::Duration as CookieDuration;
@@ -15,8 +15,20 @@ use std::sync::Arc;
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
-use crate::security::{password, totp};
-use crate::security::{rate_limit, risk};
+use crate::domain::consent::{self, PolicyDocument};
+use crate::middleware::auth::cookie_token;
+use crate::middleware::step_up::{StepUpPolicy, require_step_up};
+use crate::security::captcha::CaptchaAnswer;
+use crate::security::client_ip::ClientIp;
+use crate::security::config::AuthCookie;
+use crate::security::device::{self, ClientInfo, DeviceCheck};
+use crate::security::email_address::EmailAddress;
+use crate::security::email_policy::EmailPolicyError;
+use crate::security::jwt::{AuthContext, Claims};
+use crate::security::passkey::{self, PasskeyError, RelyingParty};
+use crate::security::rate_limit;
+use crate::security::risk::{self, RiskContext, RiskDecision};
+use crate::security::{bff, challenge, csrf, events, lockout, password, session, stuffing, totp};
 use crate::state::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
@@ -27,8 +39,33 @@ pub fn router() -> Router<Arc<AppState>> {
         .route("/auth/logout", post(logout))
         .route("/auth/request-password-reset", post(request_password_reset))
         .route("/auth/reset-password", post(reset_password))
+        .route("/auth/consent", post(accept_consent))
+        .route("/auth/sessions/report", post(report_session))
+        .route("/auth/unlock", post(unlock_account))
+        .route("/auth/challenge", get(get_challenge))
+        .route("/policies", get(current_policies))
+        .route("/auth/csrf", get(csrf_token))
+}
+
+/// Routes that need an authenticated caller; `auth_middleware` is layered on
+/// by the parent router.
+pub fn protected_router() -> Router<Arc<AppState>> {
+    Router::new()
         .route("/auth/mfa/totp/setup", post(mfa_setup))
         .route("/auth/mfa/totp/verify", post(mfa_verify))
+        .route(
+            "/auth/passkeys/register/options",
+            post(passkey_registration_options),
+        )
+        .route("/auth/passkeys/register", post(register_passkey))
+        .route("/auth/passkeys/:id", delete(delete_passkey))
+        .route_layer(axum::middleware::from_fn_with_state(
+            StepUpPolicy::default(),
+            require_step_up,
+        ))
+        .route("/auth/passkeys", get(list_passkeys))
+        .route("/auth/reauthenticate", post(reauthenticate))
+        .route("/auth/reauthenticate/passkey", post(reauthenticate_options))
 }
 
 #[derive(Deserialize)]
@@ -36,206 +73,773 @@ struct RegisterPayload {
     email: String,
     password: String,
     name: Option<String>,
+    /// Ids of the policy documents shown to the user; every current mandatory
+    /// document must be among them.
+    #[serde(default)]
+    accepted_documents: Vec<Uuid>,
+    /// Answer to a previously offered challenge, when the last attempt got
+    /// `challenge_required`.
+    captcha: Option<CaptchaAnswer>,
 }
 
 #[derive(Serialize)]
 struct TokenResponse {
-    access_token: String,
-    refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    access_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    /// Echoed so cross-origin SPAs, which cannot read the API's cookies,
+    /// can still send the `X-CSRF-Token` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    csrf_token: Option<String>,
+    /// Something the client must do before the session is fully usable,
+    /// e.g. `mfa_enrollment`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action_required: Option<&'static str>,
 }
 
-const REFRESH_TTL_DAYS: i64 = 30;
-
-fn validate_email(email: &str) -> bool {
+/// Loose shape check for sign-in: accounts created before addresses were
+/// parsed strictly must still be able to log in. New addresses go through
+/// the email policy instead.
+pub fn validate_email(email: &str) -> bool {
     email.contains('@') && email.len() <= 255
 }
 
-fn validate_password(password: &str) -> bool {
-    password.len() >= 12
+/// What to look an account up by: the email as typed, for accounts stored
+/// before addresses were normalized, and its [`EmailAddress::key`], which
+/// `lower(email)` of any account for the same mailbox equals.
+fn email_lookup_forms(email: &str) -> (String, String) {
+    let key = EmailAddress::parse(email)
+        .map(|a| a.key())
+        .unwrap_or_else(|| email.to_lowercase());
+    (email.to_string(), key)
+}
+
+/// 400 with the policy's reason code; database trouble is a 500.
+pub fn email_policy_error(e: EmailPolicyError) -> (StatusCode, String) {
+    match e {
+        EmailPolicyError::Db(e) => internal_error(e),
+        other => (StatusCode::BAD_REQUEST, other.to_string()),
+    }
 }
 
 async fn register(
     State(state): State<std::sync::Arc<AppState>>,
-    headers: HeaderMap,
+    client: ClientInfo,
     Json(payload): Json<RegisterPayload>,
 ) -> Result<Response, (StatusCode, String)> {
-    if let Some(ip) = risk::extract_ip(&headers) {
-        if !rate_limit::check(&ip, 20, 60) {
-            return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
-        }
-        match risk::risk_check(
+    if let Some(ip) = &client.ip
+        && !rate_limit::check(ip, 20, 60)
+    {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
+    }
+    let assessment = state
+        .risk
+        .assess(
             &state.db,
-            None,
-            Some(&ip),
-            headers.get("user-agent").and_then(|h| h.to_str().ok()),
+            &RiskContext {
+                action: "register",
+                user_id: None,
+                ip: client.ip.as_deref(),
+                user_agent: client.user_agent.as_deref(),
+                geo: client.geo.as_ref(),
+                device: None,
+            },
         )
-        .await
-        {
-            risk::RiskDecision::Allow => {}
-            risk::RiskDecision::Block(reason) => {
-                return Err((StatusCode::FORBIDDEN, reason.into()));
+        .await;
+    match assessment.decision {
+        RiskDecision::Allow => {}
+        RiskDecision::Challenge(reason) => {
+            if !solved_challenge(&state, &client, payload.captcha.as_ref()).await {
+                return Ok(challenge_required(&state, reason));
             }
         }
+        RiskDecision::Block(reason) => return Err((StatusCode::FORBIDDEN, reason.into())),
     }
-    if !validate_email(&payload.email) {
-        return Err((StatusCode::BAD_REQUEST, "Invalid email".into()));
+    // Stored as its uniqueness key (all lowercase, punycode domain) so case
+    // and IDN spellings of one mailbox cannot become separate accounts.
+    let email = state
+        .email_policy
+        .check(&state.db, &payload.email)
+        .await
+        .map_err(email_policy_error)?
+        .key();
+    if !password::meets_policy(&payload.password) {
+        return Err((StatusCode::BAD_REQUEST, password::POLICY_MESSAGE.into()));
     }
-    if !validate_password(&payload.password) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Password too weak (min 12 chars)".into(),
-        ));
+
+    let required = consent::current(&state.db)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter(|doc| doc.mandatory);
+    for doc in required {
+        if !payload.accepted_documents.contains(&doc.id) {
+            return Err((StatusCode::BAD_REQUEST, "consent_required".into()));
+        }
     }
 
     let hash = password::hash_password(&payload.password).map_err(internal_error)?;
     let user_id = Uuid::new_v4();
+    let safe_mode = state.security.enumeration_safe_registration;
 
     let res = sqlx::query(
         "INSERT INTO users (id, email, password_hash, name, role, created_at, updated_at, banned)
          VALUES ($1, $2, $3, $4, 'user', now(), now(), false)",
     )
     .bind(user_id)
-    .bind(&payload.email)
+    .bind(&email)
     .bind(&hash)
     .bind(&payload.name)
     .execute(&state.db)
     .await;
 
-    if let Err(e) = res {
-        return Err(map_db_error(e));
+    match res {
+        Err(e) if safe_mode && is_unique_violation(&e) => {
+            // Tell the mailbox owner, not the caller, that the email is taken.
+            let state = state.clone();
+            tokio::spawn(async move {
+                state
+                    .mailer
+                    .send(
+                        &email,
+                        "You already have an account",
+                        &format!(
+                            "Someone tried to create an account with this email, but you already have one.\n\nSign in as usual, or if you forgot your password reset it here: {}\n\nIf this wasn't you, you can ignore this email.",
+                            state.mailer.link("/forgot-password"),
+                        ),
+                    )
+                    .await;
+            });
+            return Ok(check_email());
+        }
+        Err(e) => return Err(map_db_error(e)),
+        Ok(_) => {}
     }
 
-    let access = state
-        .jwt
-        .issue_access(&user_id.to_string(), Some("user".into()))
+    consent::record(
+        &state.db,
+        user_id,
+        &payload.accepted_documents,
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    device::remember(&state.db, user_id, &client)
+        .await
         .map_err(internal_error)?;
-    let (refresh_token, refresh_hash) = generate_refresh_token();
-    store_refresh_token(
+
+    if safe_mode {
+        // No session: the caller signs in once they know which case applied.
+        let state = state.clone();
+        tokio::spawn(async move {
+            state
+                .mailer
+                .send(
+                    &email,
+                    "Welcome",
+                    &format!(
+                        "Your account is ready. Sign in here: {}",
+                        state.mailer.link("/login"),
+                    ),
+                )
+                .await;
+        });
+        return Ok(check_email());
+    }
+
+    issue_session(
         &state,
         user_id,
-        &refresh_hash,
-        headers
-            .get("user-agent")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string()),
-        risk::extract_ip(&headers),
+        "user",
+        AuthContext::now(&["pwd"]),
+        &client,
+        DeviceCheck::default(),
         None,
     )
-    .await?;
+    .await
+}
 
-    Ok(token_response(access, refresh_token, &state))
+/// The one answer enumeration-safe registration gives, whether the account
+/// was created or already existed.
+fn check_email() -> Response {
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "status": "check_email" })),
+    )
+        .into_response()
 }
 
 #[derive(Deserialize)]
 struct LoginPayload {
     email: String,
     password: String,
+    totp_code: Option<String>,
+    captcha: Option<CaptchaAnswer>,
 }
 
 async fn login(
     State(state): State<std::sync::Arc<AppState>>,
-    headers: HeaderMap,
+    client: ClientInfo,
     Json(payload): Json<LoginPayload>,
 ) -> Result<Response, (StatusCode, String)> {
-    if let Some(ip) = risk::extract_ip(&headers) {
-        if !rate_limit::check(&ip, 30, 60) {
-            return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
-        }
+    if let Some(ip) = &client.ip
+        && !rate_limit::check(ip, 30, 60)
+    {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
     }
     if !validate_email(&payload.email) {
         return Err((StatusCode::BAD_REQUEST, "Invalid email".into()));
     }
+    let (typed, key) = email_lookup_forms(&payload.email);
+    let (ip, ua) = (client.ip.clone(), client.user_agent.clone());
+    let log_failure = |user_id: Option<Uuid>, reason: &'static str| {
+        record_login_failure(&state, &client, &payload.email, user_id, reason)
+    };
 
-    let row = sqlx::query("SELECT id, password_hash, role, banned FROM users WHERE email = $1")
-        .bind(&payload.email)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(internal_error)?;
+    // Answers are single-use, so remember one that already passed.
+    let mut human = false;
+    match stuffing::gate(&state.db, &client).await {
+        RiskDecision::Allow => {}
+        RiskDecision::Challenge(reason) => {
+            if !solved_challenge(&state, &client, payload.captcha.as_ref()).await {
+                log_failure(None, "source_challenged").await;
+                return Ok(challenge_required(&state, reason));
+            }
+            human = true;
+        }
+        RiskDecision::Block(reason) => {
+            log_failure(None, "source_blocked").await;
+            return Err((StatusCode::FORBIDDEN, reason.into()));
+        }
+    }
+    if let Some(ip) = client.ip.as_deref()
+        && let Some(until) = lockout::ip_locked_until(&state.db, ip)
+            .await
+            .map_err(internal_error)?
+    {
+        log_failure(None, "locked_out").await;
+        return Ok(locked_out(until));
+    }
 
-    let row = match row {
-        Some(r) => r,
-        None => return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into())),
+    let row = sqlx::query(
+        "SELECT u.id, u.password_hash, u.role, u.banned, u.deletion_scheduled_for, u.locked_until,
+                coalesce(u.password_reset_required, false) AS password_reset_required, t.secret_b32, coalesce(t.enabled, false) AS totp_enabled
+         FROM users u LEFT JOIN mfa_totp t ON t.user_id = u.id
+         WHERE u.email = $1 OR lower(u.email) = $2
+         ORDER BY u.email = $1 DESC LIMIT 1",
+    )
+    .bind(&typed)
+    .bind(&key)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    // The password is checked before anything about the account is revealed,
+    // and unknown emails pay for a dummy verification, so neither the status
+    // code nor the response time says whether an email is registered.
+    let stored_hash: Option<String> = row.as_ref().map(|r| r.get("password_hash"));
+    let valid = password::verify_or_dummy(&payload.password, stored_hash.as_deref());
+    let user_id: Option<Uuid> = row.as_ref().map(|r| r.get("id"));
+
+    // Risk is judged before the password result is used, and an unproven
+    // challenge is answered the same way whether the password was right, so
+    // the challenge is no oracle for guessed passwords. An unknown email is
+    // scored like a known account seen from a new device, which is how every
+    // real account looks to an attacker.
+    let device = match user_id {
+        Some(user_id) => device::assess(&state.db, user_id, &client)
+            .await
+            .map_err(internal_error)?,
+        None => DeviceCheck::first_seen(&client),
     };
+    let assessment = state
+        .risk
+        .assess(
+            &state.db,
+            &RiskContext {
+                action: "login",
+                user_id,
+                ip: ip.as_deref(),
+                user_agent: ua.as_deref(),
+                geo: client.geo.as_ref(),
+                device: Some(device),
+            },
+        )
+        .await;
+    // A ban only says something once the caller has proven who they are,
+    // and the challenge names no signal: those are facts about the account.
+    let unproven = state
+        .risk
+        .decision_without(&assessment, &[risk::USER_BANNED]);
+    if let RiskDecision::Challenge(_) | RiskDecision::Block(_) = unproven
+        && !human
+    {
+        // A second factor is the stronger proof, but only counts alongside
+        // the right password.
+        let otp_proven = valid
+            && row.as_ref().is_some_and(|r| {
+                r.get::<bool, _>("totp_enabled")
+                    && payload.totp_code.as_deref().is_some_and(|code| {
+                        totp::verify_totp(&r.get::<String, _>("secret_b32"), code, 30, 6).is_ok()
+                    })
+            });
+        if !otp_proven && !solved_challenge(&state, &client, payload.captcha.as_ref()).await {
+            log_failure(user_id, "risk_challenged").await;
+            return Ok(challenge_required(&state, "risk"));
+        }
+    }
 
+    let Some(row) = row else {
+        return count_unknown_failure(&state, &payload.email, log_failure).await;
+    };
     let user_id: Uuid = row.get("id");
-    let stored_hash: String = row.get("password_hash");
     let role: String = row.get("role");
-    let banned: bool = row.get("banned");
-    if banned {
+    if let Some(until) = row.get::<Option<OffsetDateTime>, _>("locked_until")
+        && until > OffsetDateTime::now_utc()
+    {
+        log_failure(Some(user_id), "locked_out").await;
+        return Ok(locked_out(until));
+    }
+    if !valid {
+        log_failure(Some(user_id), "invalid_password").await;
+        return count_user_failure(
+            &state,
+            &client,
+            user_id,
+            &payload.email,
+            "Invalid credentials",
+        )
+        .await;
+    }
+    if row.get::<bool, _>("banned") {
+        log_failure(Some(user_id), "banned").await;
         return Err((StatusCode::FORBIDDEN, "User banned".into()));
     }
+    // Set when the user reported a sign-in as not theirs.
+    if row.get::<bool, _>("password_reset_required") {
+        log_failure(Some(user_id), "password_reset_required").await;
+        return Err((StatusCode::FORBIDDEN, "password_reset_required".into()));
+    }
 
-    let valid =
-        password::verify_password(&payload.password, &stored_hash).map_err(internal_error)?;
-    if !valid {
-        sqlx::query("UPDATE users SET failed_login_count = coalesce(failed_login_count,0)+1, last_failed_at = now() WHERE id = $1")
-            .bind(user_id)
-            .execute(&state.db)
-            .await
-            .ok();
-        return Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into()));
+    // Second factor: required whenever the user has enrolled one.
+    let totp_enabled: bool = row.get("totp_enabled");
+    let mut methods = vec!["pwd"];
+    if totp_enabled {
+        let Some(code) = payload.totp_code.as_deref() else {
+            return Err((StatusCode::UNAUTHORIZED, "mfa_required".into()));
+        };
+        let secret: String = row.get("secret_b32");
+        if totp::verify_totp(&secret, code, 30, 6).is_err() {
+            log_failure(Some(user_id), "invalid_mfa_code").await;
+            return count_user_failure(&state, &client, user_id, &payload.email, "Invalid code")
+                .await;
+        }
+        methods.push("otp");
+    }
+    let action_required =
+        (!totp_enabled && state.security.role_requires_mfa(&role)).then_some("mfa_enrollment");
+
+    // Only now, past a solved challenge and the right credentials, is a block
+    // revealed.
+    if let RiskDecision::Block(reason) = assessment.decision {
+        log_failure(Some(user_id), "risk_blocked").await;
+        return Err((StatusCode::FORBIDDEN, reason.into()));
     }
 
-    let ip = risk::extract_ip(&headers);
-    match risk::risk_check(
+    sqlx::query(
+        "UPDATE users SET failed_login_count = 0, last_failed_at = NULL, locked_until = NULL WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    .ok();
+
+    // Logging in during the grace period withdraws a pending deletion.
+    if row
+        .get::<Option<OffsetDateTime>, _>("deletion_scheduled_for")
+        .is_some()
+    {
+        cancel_pending_deletion(&state, user_id, &client).await?;
+    }
+
+    events::login_attempt(
         &state.db,
+        &state.security.identifier_hash_key,
         Some(user_id),
-        ip.as_deref(),
-        headers.get("user-agent").and_then(|h| h.to_str().ok()),
+        &payload.email,
+        &client,
+        None,
+    )
+    .await;
+
+    let auth = AuthContext::now(&methods);
+    let pending = consent::pending(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+    if !pending.is_empty() {
+        let token = challenge::create(&state.db, user_id, CONSENT_CHALLENGE, &auth)
+            .await
+            .map_err(internal_error)?;
+        return Ok(consent_required(token, pending));
+    }
+
+    device::remember(&state.db, user_id, &client)
+        .await
+        .map_err(internal_error)?;
+    issue_session(
+        &state,
+        user_id,
+        &role,
+        auth,
+        &client,
+        device,
+        action_required,
     )
     .await
+}
+
+async fn record_login_failure(
+    state: &AppState,
+    client: &ClientInfo,
+    email: &str,
+    user_id: Option<Uuid>,
+    reason: &'static str,
+) {
+    events::login_attempt(
+        &state.db,
+        &state.security.identifier_hash_key,
+        user_id,
+        email,
+        client,
+        Some(reason),
+    )
+    .await;
+    // Only guesses count toward the stuffing limits and the address lockout.
+    if !stuffing::CREDENTIAL_FAILURES.contains(&reason) {
+        return;
+    }
+    stuffing::observe_failure(&state.db, client).await;
+    if let Some(ip) = client.ip.as_deref()
+        && let Err(e) = lockout::record_ip_failure(&state.db, &state.security.ip_lockout, ip).await
     {
-        risk::RiskDecision::Allow => {}
-        risk::RiskDecision::Block(reason) => return Err((StatusCode::FORBIDDEN, reason.into())),
+        tracing::warn!("failed to count login failure for {ip}: {e}");
     }
+}
 
-    sqlx::query("UPDATE users SET failed_login_count = 0, last_failed_at = NULL WHERE id = $1")
-        .bind(user_id)
-        .execute(&state.db)
+/// The unknown-email twin of [`count_user_failure`]: the same lock after the
+/// same number of tries, so the lockout does not reveal whether the email has
+/// an account. No unlock email, as there is no one to send it to.
+async fn count_unknown_failure<F, Fut>(
+    state: &AppState,
+    email: &str,
+    log_failure: F,
+) -> Result<Response, (StatusCode, String)>
+where
+    F: Fn(Option<Uuid>, &'static str) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let key = stuffing::identifier_hash(&state.security.identifier_hash_key, email);
+    if let Some(until) = lockout::identifier_locked_until(&state.db, &key)
         .await
-        .ok();
+        .map_err(internal_error)?
+    {
+        log_failure(None, "locked_out").await;
+        return Ok(locked_out(until));
+    }
+    log_failure(None, "unknown_user").await;
+    let lock = lockout::record_identifier_failure(&state.db, &state.security.user_lockout, &key)
+        .await
+        .map_err(internal_error)?;
+    match lock.locked_until {
+        Some(until) => Ok(locked_out(until)),
+        None => Err((StatusCode::UNAUTHORIZED, "Invalid credentials".into())),
+    }
+}
 
-    let ua = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-    let _ = sqlx::query("INSERT INTO login_logs (id, user_id, ip, user_agent, success, created_at) VALUES ($1, $2, $3, $4, true, now())")
-        .bind(Uuid::new_v4())
-        .bind(user_id)
-        .bind(ip.clone())
-        .bind(ua.clone())
-        .execute(&state.db)
+/// Counts a wrong password or code against the account. Once that locks it,
+/// the answer becomes the lockout itself, and the owner is told the first
+/// time in a streak.
+pub async fn count_user_failure(
+    state: &Arc<AppState>,
+    client: &ClientInfo,
+    user_id: Uuid,
+    email: &str,
+    message: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let policy = &state.security.user_lockout;
+    let lock = lockout::record_user_failure(&state.db, policy, user_id)
+        .await
+        .map_err(internal_error)?;
+    if lock.just_locked(policy) {
+        // In the background, so the locking attempt takes no longer than the
+        // same attempt against an unknown email.
+        let (state, client, email) = (state.clone(), client.clone(), email.to_string());
+        tokio::spawn(async move {
+            if let Err(e) = notify_lockout(&state, &client, user_id, &email, lock.failures).await {
+                tracing::error!("failed to notify {user_id} of lockout: {e}");
+            }
+        });
+    }
+    match lock.locked_until {
+        Some(until) => Ok(locked_out(until)),
+        None => Err((StatusCode::UNAUTHORIZED, message.into())),
+    }
+}
+
+async fn notify_lockout(
+    state: &AppState,
+    client: &ClientInfo,
+    user_id: Uuid,
+    email: &str,
+    failures: i64,
+) -> Result<(), sqlx::Error> {
+    let token = lockout::issue_unlock_token(&state.db, user_id).await?;
+    events::record(
+        &state.db,
+        user_id,
+        "account_locked",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({ "failures": failures }),
+    )
+    .await;
+    state
+        .mailer
+        .send(
+            email,
+            "Your account was temporarily locked",
+            &format!(
+                "Sign-in to your account was paused after {failures} failed attempts.\n\nIf that was you, unlock it now: {}\n\nIf it wasn't, someone may be guessing your password. Reset it here: {}",
+                state.mailer.link(&format!("/unlock?token={token}")),
+                state.mailer.link("/forgot-password"),
+            ),
+        )
         .await;
+    Ok(())
+}
 
-    let access = state
-        .jwt
-        .issue_access(&user_id.to_string(), Some(role))
+/// 429 with `Retry-After`. Accounts, unknown emails and addresses get the
+/// same answer so it does not reveal which one is locked, or whether the
+/// email has an account.
+pub fn locked_out(until: OffsetDateTime) -> Response {
+    let retry_after = (until - OffsetDateTime::now_utc()).whole_seconds().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        Json(serde_json::json!({
+            "error": "locked_out",
+            "retry_after": retry_after,
+        })),
+    )
+        .into_response()
+}
+
+async fn solved_challenge(
+    state: &AppState,
+    client: &ClientInfo,
+    answer: Option<&CaptchaAnswer>,
+) -> bool {
+    match answer {
+        Some(answer) => {
+            state
+                .risk
+                .captcha()
+                .verify(&state.db, answer, client.ip.as_deref())
+                .await
+        }
+        None => false,
+    }
+}
+
+/// Asks the client to prove it is a person and retry with the answer in its
+/// payload's `captcha` field.
+fn challenge_required(state: &AppState, reason: &str) -> Response {
+    let mut body = state.risk.captcha().offer();
+    body["error"] = "challenge_required".into();
+    body["reason"] = reason.into();
+    (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+}
+
+/// Hands out a challenge up front, for clients that would rather solve it
+/// before a risky request than retry after one.
+async fn get_challenge(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(state.risk.captcha().offer())
+}
+
+#[derive(Deserialize)]
+struct UnlockPayload {
+    token: String,
+}
+
+/// Target of the link in the lockout email.
+async fn unlock_account(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<UnlockPayload>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let user_id = lockout::redeem_unlock_token(&state.db, &payload.token)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired token".into()))?;
+    events::record(
+        &state.db,
+        user_id,
+        "account_unlocked",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({ "via": "email" }),
+    )
+    .await;
+    Ok("account unlocked")
+}
+
+const CONSENT_CHALLENGE: &str = "consent";
+
+/// Returned instead of tokens when a newer mandatory policy version awaits
+/// acceptance; the client completes the login via `/auth/consent`.
+fn consent_required(token: String, documents: Vec<PolicyDocument>) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({
+            "error": "consent_required",
+            "consent_token": token,
+            "documents": documents,
+        })),
+    )
+        .into_response()
+}
+
+async fn current_policies(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PolicyDocument>>, (StatusCode, String)> {
+    consent::current(&state.db)
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+#[derive(Deserialize)]
+struct AcceptConsentPayload {
+    consent_token: String,
+    accepted_documents: Vec<Uuid>,
+}
+
+async fn accept_consent(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<AcceptConsentPayload>,
+) -> Result<Response, (StatusCode, String)> {
+    let parked = challenge::find(&state.db, &payload.consent_token, CONSENT_CHALLENGE)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired token".into()))?;
+
+    consent::record(
+        &state.db,
+        parked.user_id,
+        &payload.accepted_documents,
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+    )
+    .await
+    .map_err(internal_error)?;
+    let pending = consent::pending(&state.db, parked.user_id)
+        .await
+        .map_err(internal_error)?;
+    if !pending.is_empty() {
+        return Ok(consent_required(payload.consent_token, pending));
+    }
+    if !challenge::consume(&state.db, parked.id)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid or expired token".into()));
+    }
+
+    let row = sqlx::query(
+        "SELECT u.role, u.banned, coalesce(t.enabled, false) AS totp_enabled
+         FROM users u LEFT JOIN mfa_totp t ON t.user_id = u.id
+         WHERE u.id = $1",
+    )
+    .bind(parked.user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::UNAUTHORIZED, "Unknown user".into()))?;
+    if row.get::<bool, _>("banned") {
+        return Err((StatusCode::FORBIDDEN, "User banned".into()));
+    }
+    let role: String = row.get("role");
+    let action_required = (!row.get::<bool, _>("totp_enabled")
+        && state.security.role_requires_mfa(&role))
+    .then_some("mfa_enrollment");
+
+    let device = device::check_and_remember(&state.db, parked.user_id, &client)
+        .await
         .map_err(internal_error)?;
-    let (refresh_token, refresh_hash) = generate_refresh_token();
-    store_refresh_token(&state, user_id, &refresh_hash, ua.clone(), ip.clone(), None).await?;
+    issue_session(
+        &state,
+        parked.user_id,
+        &role,
+        parked.auth,
+        &client,
+        device,
+        action_required,
+    )
+    .await
+}
 
-    Ok(token_response(access, refresh_token, &state))
+/// Withdraws a deletion still in its grace period; the owner signing back in
+/// is taken as changing their mind.
+async fn cancel_pending_deletion(
+    state: &AppState,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> Result<(), (StatusCode, String)> {
+    let res = sqlx::query(
+        "UPDATE users SET deletion_requested_at = NULL, deletion_scheduled_for = NULL, updated_at = now()
+         WHERE id = $1 AND deletion_scheduled_for IS NOT NULL",
+    )
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    .map_err(internal_error)?;
+    if res.rows_affected() > 0 {
+        record_event(state, user_id, "account_deletion_cancelled", client).await;
+    }
+    Ok(())
 }
 
 #[derive(Deserialize)]
 struct RefreshPayload {
-    refresh_token: String,
+    refresh_token: Option<String>,
 }
 
 async fn refresh(
     State(state): State<std::sync::Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
     headers: HeaderMap,
-    Json(payload): Json<RefreshPayload>,
+    payload: Option<Json<RefreshPayload>>,
 ) -> Result<Response, (StatusCode, String)> {
-    let ip = risk::extract_ip(&headers);
-    if let Some(ref ip) = ip {
-        if !rate_limit::check(&ip, 60, 60) {
-            return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
-        }
+    let ip = client_ip.to_string();
+    if !rate_limit::check(&ip, 60, 60) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
     }
-    let hash = hash_refresh_token(&payload.refresh_token);
+    let Some(presented) =
+        presented_refresh_token(&state, &headers, payload.and_then(|p| p.0.refresh_token))
+    else {
+        return Err((StatusCode::UNAUTHORIZED, "Missing refresh token".into()));
+    };
+    let hash = hash_refresh_token(&presented);
     let row = sqlx::query(
-        "SELECT user_id, revoked_at, expires_at, id FROM refresh_tokens WHERE token_hash = $1",
+        "SELECT r.user_id, r.revoked_at, r.expires_at, r.id, r.created_at, r.session_id, u.role,
+                coalesce(r.session_started_at, r.created_at) AS session_started_at,
+                coalesce(r.auth_time, coalesce(r.session_started_at, r.created_at)) AS auth_time,
+                coalesce(r.amr, '{}') AS amr
+         FROM refresh_tokens r JOIN users u ON u.id = r.user_id
+         WHERE r.token_hash = $1",
     )
     .bind(&hash)
     .fetch_optional(&state.db)
@@ -249,32 +853,57 @@ async fn refresh(
 
     let revoked: Option<OffsetDateTime> = row.get("revoked_at");
     let expires_at: OffsetDateTime = row.get("expires_at");
-    if revoked.is_some() || expires_at < OffsetDateTime::now_utc() {
+    let now = OffsetDateTime::now_utc();
+    if revoked.is_some() || expires_at < now {
         return Err((StatusCode::UNAUTHORIZED, "Token expired/revoked".into()));
     }
 
+    // Re-check against the current config so shortened timeouts apply to
+    // tokens issued before the change.
+    let last_refreshed_at: OffsetDateTime = row.get("created_at");
+    let session_started_at: OffsetDateTime = row.get("session_started_at");
+    if now - last_refreshed_at > state.security.session_idle_timeout {
+        return Err((StatusCode::UNAUTHORIZED, "Session idle timeout".into()));
+    }
+    if now - session_started_at > state.security.session_absolute_timeout {
+        return Err((StatusCode::UNAUTHORIZED, "Session expired".into()));
+    }
+
     let user_id: Uuid = row.get("user_id");
-    match risk::risk_check(
-        &state.db,
-        Some(user_id),
-        ip.as_deref(),
-        headers.get("user-agent").and_then(|h| h.to_str().ok()),
-    )
-    .await
-    {
-        risk::RiskDecision::Allow => {}
-        risk::RiskDecision::Block(reason) => return Err((StatusCode::FORBIDDEN, reason.into())),
+    let geo = state.geoip.lookup(&ip);
+    let assessment = state
+        .risk
+        .assess(
+            &state.db,
+            &RiskContext {
+                action: "refresh",
+                user_id: Some(user_id),
+                ip: Some(&ip),
+                user_agent: headers.get("user-agent").and_then(|h| h.to_str().ok()),
+                geo: geo.as_ref(),
+                device: None,
+            },
+        )
+        .await;
+    // There is nobody to challenge on a background refresh; only hard blocks apply.
+    if let RiskDecision::Block(reason) = assessment.decision {
+        return Err((StatusCode::FORBIDDEN, reason.into()));
     }
+    let auth = AuthContext {
+        auth_time: row.get("auth_time"),
+        amr: row.get("amr"),
+    };
     let access = state
         .jwt
-        .issue_access(&user_id.to_string(), Some("user".into()))
+        .issue_access(&user_id.to_string(), Some(row.get("role")), &auth)
         .map_err(internal_error)?;
 
     // rotate
     let (new_refresh, new_hash) = generate_refresh_token();
     let old_id: Uuid = row.get("id");
+    let session_id: Option<Uuid> = row.get("session_id");
     revoke_refresh_token(&state, old_id).await?;
-    store_refresh_token(
+    let refresh_expires_at = store_refresh_token(
         &state,
         user_id,
         &new_hash,
@@ -282,12 +911,28 @@ async fn refresh(
             .get("user-agent")
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string()),
-        risk::extract_ip(&headers),
-        Some(old_id),
+        Some(ip),
+        &SessionLineage {
+            rotated_from: Some(old_id),
+            session_id,
+            started_at: session_started_at,
+            auth: &auth,
+        },
     )
     .await?;
+    if let Some(session_id) = session_id {
+        session::touch(&state.db, session_id)
+            .await
+            .map_err(internal_error)?;
+    }
 
-    Ok(token_response(access, new_refresh, &state))
+    Ok(token_response(
+        access,
+        new_refresh,
+        refresh_expires_at,
+        None,
+        &state,
+    ))
 }
 
 #[derive(Deserialize)]
@@ -297,9 +942,12 @@ struct LogoutPayload {
 
 async fn logout(
     State(state): State<std::sync::Arc<AppState>>,
-    Json(payload): Json<LogoutPayload>,
+    headers: HeaderMap,
+    payload: Option<Json<LogoutPayload>>,
 ) -> Result<Response, (StatusCode, String)> {
-    if let Some(rt) = payload.refresh_token {
+    if let Some(rt) =
+        presented_refresh_token(&state, &headers, payload.and_then(|p| p.0.refresh_token))
+    {
         let hash = hash_refresh_token(&rt);
         let _ = sqlx::query("UPDATE refresh_tokens SET revoked_at = now() WHERE token_hash = $1")
             .bind(&hash)
@@ -307,10 +955,28 @@ async fn logout(
             .await
             .map_err(internal_error)?;
     }
-    let mut res = Json(TokenResponse {
-        access_token: "".into(),
-        refresh_token: "".into(),
-    })
+    if state.security.bff_mode
+        && let Some(session) = cookie_token(&headers, &state.security.bff_cookie_name)
+    {
+        bff::destroy(&state.db, &session)
+            .await
+            .map_err(internal_error)?;
+    }
+    let mut res = if state.security.cookie_only_tokens || state.security.bff_mode {
+        Json(TokenResponse {
+            access_token: None,
+            refresh_token: None,
+            csrf_token: None,
+            action_required: None,
+        })
+    } else {
+        Json(TokenResponse {
+            access_token: Some("".into()),
+            refresh_token: Some("".into()),
+            csrf_token: None,
+            action_required: None,
+        })
+    }
     .into_response();
     clear_cookies(&mut res, &state.security);
     Ok(res)
@@ -319,21 +985,83 @@ async fn logout(
 #[derive(Deserialize)]
 struct RequestResetPayload {
     email: String,
+    captcha: Option<CaptchaAnswer>,
 }
 
 async fn request_password_reset(
     State(state): State<Arc<AppState>>,
+    client: ClientInfo,
     Json(payload): Json<RequestResetPayload>,
-) -> Result<&'static str, (StatusCode, String)> {
-    let row = sqlx::query("SELECT id FROM users WHERE email = $1")
-        .bind(&payload.email)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(internal_error)?;
-    let Some(user_id): Option<Uuid> = row.map(|r| r.get("id")) else {
-        return Ok("reset requested");
+) -> Result<Response, (StatusCode, String)> {
+    let assessment = state
+        .risk
+        .assess(
+            &state.db,
+            &RiskContext {
+                action: "password_reset",
+                user_id: None,
+                ip: client.ip.as_deref(),
+                user_agent: client.user_agent.as_deref(),
+                geo: client.geo.as_ref(),
+                device: None,
+            },
+        )
+        .await;
+    match assessment.decision {
+        RiskDecision::Allow => {}
+        RiskDecision::Challenge(reason) => {
+            if !solved_challenge(&state, &client, payload.captcha.as_ref()).await {
+                return Ok(challenge_required(&state, reason));
+            }
+        }
+        RiskDecision::Block(reason) => return Err((StatusCode::FORBIDDEN, reason.into())),
+    }
+
+    // The lookup, token and email happen after the response so known and
+    // unknown emails get the same answer in the same time.
+    tokio::spawn(async move {
+        if let Err((_, e)) = send_password_reset(&state, &payload.email).await {
+            tracing::error!("password reset request failed: {e}");
+        }
+    });
+    Ok("reset requested".into_response())
+}
+
+async fn send_password_reset(state: &AppState, email: &str) -> Result<(), (StatusCode, String)> {
+    let (typed, key) = email_lookup_forms(email);
+    let account: Option<(Uuid, String)> = sqlx::query_as(
+        "SELECT id, email FROM users WHERE email = $1 OR lower(email) = $2
+         ORDER BY email = $1 DESC LIMIT 1",
+    )
+    .bind(&typed)
+    .bind(&key)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?;
+    let Some((user_id, stored_email)) = account else {
+        return Ok(());
     };
+    let token = create_password_reset(state, user_id).await?;
+    // To the address on file, not the spelling the requester typed.
+    state
+        .mailer
+        .send(
+            &stored_email,
+            "Reset your password",
+            &format!(
+                "Someone asked to reset the password for this account. If it was you, choose a new one here:\n\n{}\n\nIf it wasn't, you can ignore this email.",
+                state.mailer.link(&format!("/reset-password?token={token}")),
+            ),
+        )
+        .await;
+    Ok(())
+}
 
+/// Issues a single-use reset token for the user, replacing any earlier one.
+async fn create_password_reset(
+    state: &AppState,
+    user_id: Uuid,
+) -> Result<String, (StatusCode, String)> {
     let (token, token_hash) = generate_refresh_token();
     let expires_at = OffsetDateTime::now_utc() + Duration::minutes(30);
     sqlx::query("INSERT INTO password_resets (user_id, token_hash, expires_at, used) VALUES ($1, $2, $3, false)
@@ -344,13 +1072,7 @@ async fn request_password_reset(
         .execute(&state.db)
         .await
         .map_err(internal_error)?;
-
-    tracing::info!(
-        "Password reset token issued for {}: {}",
-        payload.email,
-        token
-    );
-    Ok("reset requested")
+    Ok(token)
 }
 
 #[derive(Deserialize)]
@@ -361,13 +1083,11 @@ struct ResetPayload {
 
 async fn reset_password(
     State(state): State<Arc<AppState>>,
+    client: ClientInfo,
     Json(payload): Json<ResetPayload>,
 ) -> Result<Response, (StatusCode, String)> {
-    if !validate_password(&payload.new_password) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Password too weak (min 12 chars)".into(),
-        ));
+    if !password::meets_policy(&payload.new_password) {
+        return Err((StatusCode::BAD_REQUEST, password::POLICY_MESSAGE.into()));
     }
 
     let token_hash = hash_refresh_token(&payload.reset_token);
@@ -390,12 +1110,13 @@ async fn reset_password(
     let user_id: Uuid = row.get("user_id");
 
     let new_hash = password::hash_password(&payload.new_password).map_err(internal_error)?;
-    sqlx::query("UPDATE users SET password_hash = $1, failed_login_count = 0, last_failed_at = NULL WHERE id = $2")
+    let role: String = sqlx::query("UPDATE users SET password_hash = $1, failed_login_count = 0, last_failed_at = NULL, locked_until = NULL, password_reset_required = false, updated_at = now() WHERE id = $2 RETURNING role")
         .bind(new_hash)
         .bind(user_id)
-        .execute(&state.db)
+        .fetch_one(&state.db)
         .await
-        .map_err(internal_error)?;
+        .map_err(internal_error)?
+        .get("role");
 
     sqlx::query("UPDATE password_resets SET used = true WHERE token_hash = $1")
         .bind(&token_hash)
@@ -403,26 +1124,20 @@ async fn reset_password(
         .await
         .ok();
 
-    sqlx::query("UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1")
-        .bind(user_id)
-        .execute(&state.db)
-        .await
-        .ok();
-
-    let access = state
-        .jwt
-        .issue_access(&user_id.to_string(), Some("user".into()))
-        .map_err(internal_error)?;
-    let (refresh_token, refresh_hash) = generate_refresh_token();
-    store_refresh_token(&state, user_id, &refresh_hash, None, None, None).await?;
-
-    Ok(token_response(access, refresh_token, &state))
-}
+    session::revoke_all_for_user(&state.db, user_id).await.ok();
+    // The reset signs the user in, which withdraws a deletion like login does.
+    cancel_pending_deletion(&state, user_id, &client).await?;
 
-#[derive(Deserialize)]
-struct TotpSetupRequest {
-    user_id: Uuid,
-    email: String,
+    issue_session(
+        &state,
+        user_id,
+        &role,
+        AuthContext::now(&["pwd"]),
+        &client,
+        DeviceCheck::default(),
+        None,
+    )
+    .await
 }
 
 #[derive(Serialize)]
@@ -433,21 +1148,40 @@ struct TotpSetupResponse {
 
 async fn mfa_setup(
     State(state): State<std::sync::Arc<AppState>>,
-    Json(payload): Json<TotpSetupRequest>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
 ) -> Result<Json<TotpSetupResponse>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let row = sqlx::query(
+        "SELECT u.email, coalesce(t.enabled, false) AS totp_enabled
+         FROM users u LEFT JOIN mfa_totp t ON t.user_id = u.id
+         WHERE u.id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::UNAUTHORIZED, "Unknown user".into()))?;
+    // Replacing an enrolled factor disables it, so the session must have
+    // proven that factor, not just the password.
+    if row.get::<bool, _>("totp_enabled") && !claims.amr.iter().any(|m| m == "otp") {
+        return Err((StatusCode::UNAUTHORIZED, "mfa_step_up_required".into()));
+    }
+    let email: String = row.get("email");
     let secret = totp::generate_secret();
-    let url = totp::otpauth_url("Tajawal", &payload.email, &secret);
+    let url = totp::otpauth_url("Tajawal", &email, &secret);
 
     sqlx::query(
         "INSERT INTO mfa_totp (user_id, secret_b32, enabled, created_at)
          VALUES ($1, $2, false, now())
          ON CONFLICT (user_id) DO UPDATE SET secret_b32 = EXCLUDED.secret_b32, enabled = false, created_at = now()",
     )
-    .bind(payload.user_id)
+    .bind(user_id)
     .bind(&secret)
     .execute(&state.db)
     .await
     .map_err(internal_error)?;
+    record_event(&state, user_id, "mfa_enrollment_started", &client).await;
 
     Ok(Json(TotpSetupResponse {
         secret,
@@ -457,16 +1191,18 @@ async fn mfa_setup(
 
 #[derive(Deserialize)]
 struct TotpVerifyRequest {
-    user_id: Uuid,
     code: String,
 }
 
 async fn mfa_verify(
     State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
     Json(payload): Json<TotpVerifyRequest>,
 ) -> Result<&'static str, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
     let row = sqlx::query("SELECT secret_b32 FROM mfa_totp WHERE user_id = $1")
-        .bind(payload.user_id)
+        .bind(user_id)
         .fetch_optional(&state.db)
         .await
         .map_err(internal_error)?;
@@ -480,18 +1216,302 @@ async fn mfa_verify(
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid code".into()))?;
 
     sqlx::query("UPDATE mfa_totp SET enabled = true WHERE user_id = $1")
-        .bind(payload.user_id)
+        .bind(user_id)
         .execute(&state.db)
         .await
         .map_err(internal_error)?;
+    record_event(&state, user_id, "mfa_enabled", &client).await;
 
     Ok("mfa verified")
 }
 
-fn internal_error<E: std::fmt::Display>(err: E) -> (StatusCode, String) {
+/// 501 when no relying party is configured.
+fn relying_party(state: &AppState) -> Result<RelyingParty, (StatusCode, String)> {
+    RelyingParty::from_config(&state.security).ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        "passkeys_not_configured".into(),
+    ))
+}
+
+fn passkey_error(e: PasskeyError) -> (StatusCode, String) {
+    match e {
+        PasskeyError::Db(e) => internal_error(e),
+        PasskeyError::Duplicate => (StatusCode::CONFLICT, e.to_string()),
+        other => (StatusCode::BAD_REQUEST, other.to_string()),
+    }
+}
+
+async fn passkey_registration_options(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let rp = relying_party(&state)?;
+    let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::UNAUTHORIZED, "Unknown user".into()))?;
+    passkey::registration_options(&state.db, &rp, user_id, &email)
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+#[derive(Deserialize)]
+struct RegisterPasskeyPayload {
+    /// Label to tell the user's passkeys apart, e.g. "Work laptop".
+    name: Option<String>,
+    credential: passkey::RegistrationResponse,
+}
+
+async fn register_passkey(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+    Json(payload): Json<RegisterPasskeyPayload>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let rp = relying_party(&state)?;
+    let id = passkey::register(
+        &state.db,
+        &rp,
+        user_id,
+        payload.name.as_deref(),
+        &payload.credential,
+    )
+    .await
+    .map_err(passkey_error)?;
+    record_event(&state, user_id, "passkey_added", &client).await;
+    Ok(Json(serde_json::json!({ "id": id })))
+}
+
+async fn list_passkeys(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let rows = sqlx::query(
+        "SELECT id, name, created_at, last_used_at FROM passkeys WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(internal_error)?;
+    Ok(Json(
+        rows.into_iter()
+            .map(|r| {
+                serde_json::json!({
+                    "id": r.get::<Uuid, _>("id"),
+                    "name": r.get::<Option<String>, _>("name"),
+                    "created_at": r.get::<OffsetDateTime, _>("created_at"),
+                    "last_used_at": r.get::<Option<OffsetDateTime>, _>("last_used_at"),
+                })
+            })
+            .collect(),
+    ))
+}
+
+async fn delete_passkey(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let res = sqlx::query("DELETE FROM passkeys WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(&state.db)
+        .await
+        .map_err(internal_error)?;
+    if res.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Passkey not found".into()));
+    }
+    record_event(&state, user_id, "passkey_removed", &client).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Challenge for reauthenticating with a passkey; the answer goes in
+/// `/auth/reauthenticate`'s `passkey` field.
+async fn reauthenticate_options(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    if !rate_limit::check(&format!("passkey-options:{user_id}"), 10, 60) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
+    }
+    let rp = relying_party(&state)?;
+    passkey::assertion_options(&state.db, &rp, user_id)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No passkey registered".into()))
+}
+
+#[derive(Deserialize)]
+struct ReauthenticatePayload {
+    password: Option<String>,
+    totp_code: Option<String>,
+    /// Answer to the challenge from `/auth/reauthenticate/passkey`.
+    passkey: Option<passkey::AssertionResponse>,
+    refresh_token: Option<String>,
+}
+
+/// Re-proves the caller's identity and starts a fresh session whose
+/// `auth_time` satisfies step-up checks. The new session carries only the
+/// factors proven in this call; password and TOTP together, or a passkey
+/// unlocked by PIN or biometric, reach MFA. Wrong answers count toward the
+/// same lockout as sign-in.
+async fn reauthenticate(
+    State(state): State<std::sync::Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(payload): Json<ReauthenticatePayload>,
+) -> Result<Response, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    if let Some(ip) = &client.ip
+        && !rate_limit::check(&format!("reauth:{ip}"), 10, 60)
+    {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
+    }
+    // Per account as well: a stolen access token must not buy unlimited
+    // guesses from many addresses.
+    if !rate_limit::check(&format!("reauth-user:{user_id}"), 5, 60) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
+    }
+
+    let row = sqlx::query(
+        "SELECT u.email, u.password_hash, u.role, u.banned, u.locked_until, t.secret_b32,
+                coalesce(t.enabled, false) AS totp_enabled
+         FROM users u LEFT JOIN mfa_totp t ON t.user_id = u.id
+         WHERE u.id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::UNAUTHORIZED, "Invalid credentials".into()))?;
+    if row.get::<bool, _>("banned") {
+        return Err((StatusCode::FORBIDDEN, "User banned".into()));
+    }
+    if let Some(until) = row.get::<Option<OffsetDateTime>, _>("locked_until")
+        && until > OffsetDateTime::now_utc()
+    {
+        return Ok(locked_out(until));
+    }
+    let email: String = row.get("email");
+
+    let mut methods = Vec::new();
+    if let Some(pw) = &payload.password {
+        let stored_hash: String = row.get("password_hash");
+        if !password::verify_password(pw, &stored_hash).map_err(internal_error)? {
+            return count_user_failure(&state, &client, user_id, &email, "Invalid credentials")
+                .await;
+        }
+        methods.push("pwd");
+    }
+    if let Some(code) = &payload.totp_code {
+        let secret: Option<String> = row.get("secret_b32");
+        let enabled: bool = row.get("totp_enabled");
+        let Some(secret) = secret.filter(|_| enabled) else {
+            return Err((StatusCode::BAD_REQUEST, "No TOTP setup found".into()));
+        };
+        if totp::verify_totp(&secret, code, 30, 6).is_err() {
+            return count_user_failure(&state, &client, user_id, &email, "Invalid code").await;
+        }
+        methods.push("otp");
+    }
+    if let Some(assertion) = &payload.passkey {
+        let rp = relying_party(&state)?;
+        match passkey::verify(&state.db, &rp, user_id, assertion).await {
+            Ok(proof) => methods.extend(proof.methods()),
+            Err(PasskeyError::Db(e)) => return Err(internal_error(e)),
+            Err(_) => {
+                return count_user_failure(&state, &client, user_id, &email, "Invalid passkey")
+                    .await;
+            }
+        }
+    }
+    if methods.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "password, totp_code or passkey required".into(),
+        ));
+    }
+    sqlx::query(
+        "UPDATE users SET failed_login_count = 0, last_failed_at = NULL, locked_until = NULL WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    .ok();
+
+    // The reauthenticated session replaces the caller's current one.
+    if let Some(rt) = presented_refresh_token(&state, &headers, payload.refresh_token) {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = now() WHERE token_hash = $1 AND user_id = $2",
+        )
+        .bind(hash_refresh_token(&rt))
+        .bind(user_id)
+        .execute(&state.db)
+        .await
+        .map_err(internal_error)?;
+    }
+    if state.security.bff_mode
+        && let Some(session) = cookie_token(&headers, &state.security.bff_cookie_name)
+    {
+        bff::destroy(&state.db, &session)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    let role: String = row.get("role");
+    let totp_enabled: bool = row.get("totp_enabled");
+    let action_required =
+        (!totp_enabled && state.security.role_requires_mfa(&role)).then_some("mfa_enrollment");
+    issue_session(
+        &state,
+        user_id,
+        &role,
+        AuthContext::now(&methods),
+        &client,
+        DeviceCheck::default(),
+        action_required,
+    )
+    .await
+}
+
+async fn record_event(state: &AppState, user_id: Uuid, kind: &str, client: &ClientInfo) {
+    events::record(
+        &state.db,
+        user_id,
+        kind,
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({}),
+    )
+    .await;
+}
+
+pub fn claims_user_id(claims: &Claims) -> Result<Uuid, (StatusCode, String)> {
+    claims
+        .sub
+        .parse()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid subject".into()))
+}
+
+pub fn internal_error<E: std::fmt::Display>(err: E) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 }
 
+pub fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(db_err) if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation)
+}
+
 fn map_db_error(err: sqlx::Error) -> (StatusCode, String) {
     if let sqlx::Error::Database(db_err) = &err {
         if db_err.constraint().is_some() {
@@ -501,43 +1521,71 @@ fn map_db_error(err: sqlx::Error) -> (StatusCode, String) {
     internal_error(err)
 }
 
-fn generate_refresh_token() -> (String, String) {
+/// Random opaque token and its SHA-256 hash; also used for emailed links.
+pub fn generate_refresh_token() -> (String, String) {
     let raw = format!("{}-{}", Uuid::new_v4(), Uuid::new_v4());
     let hash = hash_refresh_token(&raw);
     (raw, hash)
 }
 
-fn hash_refresh_token(raw: &str) -> String {
+pub fn hash_refresh_token(raw: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(raw.as_bytes());
     let result = hasher.finalize();
     hex::encode(result)
 }
 
+/// What a refresh token inherits from the one it replaces, so the session
+/// keeps its start time and authentication context across rotations.
+struct SessionLineage<'a> {
+    rotated_from: Option<Uuid>,
+    /// `None` only for tokens issued before sessions were tracked.
+    session_id: Option<Uuid>,
+    started_at: OffsetDateTime,
+    auth: &'a AuthContext,
+}
+
+impl<'a> SessionLineage<'a> {
+    fn new(auth: &'a AuthContext, session_id: Uuid) -> Self {
+        Self {
+            rotated_from: None,
+            session_id: Some(session_id),
+            started_at: OffsetDateTime::now_utc(),
+            auth,
+        }
+    }
+}
+
 async fn store_refresh_token(
     state: &std::sync::Arc<AppState>,
     user_id: Uuid,
     token_hash: &str,
     user_agent: Option<String>,
     ip: Option<String>,
-    rotated_from: Option<Uuid>,
-) -> Result<(), (StatusCode, String)> {
-    let expires_at = OffsetDateTime::now_utc() + Duration::days(REFRESH_TTL_DAYS);
+    lineage: &SessionLineage<'_>,
+) -> Result<OffsetDateTime, (StatusCode, String)> {
+    let now = OffsetDateTime::now_utc();
+    let expires_at = state.security.refresh_expiry(lineage.started_at, now);
     sqlx::query(
-        "INSERT INTO refresh_tokens (id, user_id, token_hash, created_at, expires_at, revoked_at, user_agent, ip, rotated_from)
-         VALUES ($1, $2, $3, now(), $4, NULL, $5, $6, $7)",
+        "INSERT INTO refresh_tokens (id, user_id, token_hash, created_at, expires_at, revoked_at, user_agent, ip, rotated_from, session_started_at, auth_time, amr, session_id)
+         VALUES ($1, $2, $3, $4, $5, NULL, $6, $7, $8, $9, $10, $11, $12)",
     )
     .bind(Uuid::new_v4())
     .bind(user_id)
     .bind(token_hash)
+    .bind(now)
     .bind(expires_at)
     .bind(user_agent)
     .bind(ip)
-    .bind(rotated_from)
+    .bind(lineage.rotated_from)
+    .bind(lineage.started_at)
+    .bind(lineage.auth.auth_time)
+    .bind(&lineage.auth.amr)
+    .bind(lineage.session_id)
     .execute(&state.db)
     .await
     .map_err(internal_error)?;
-    Ok(())
+    Ok(expires_at)
 }
 
 async fn revoke_refresh_token(
@@ -552,13 +1600,278 @@ async fn revoke_refresh_token(
     Ok(())
 }
 
-fn token_response(access: String, refresh: String, state: &std::sync::Arc<AppState>) -> Response {
-    let body = Json(TokenResponse {
-        access_token: access.clone(),
-        refresh_token: refresh.clone(),
-    });
+/// Starts a new session after a successful authentication: an access/refresh
+/// token pair, or a server-side session in BFF mode. A sign-in from an
+/// unfamiliar device or network is flagged and the user is told about it.
+pub async fn issue_session(
+    state: &std::sync::Arc<AppState>,
+    user_id: Uuid,
+    role: &str,
+    auth: AuthContext,
+    client: &ClientInfo,
+    device: DeviceCheck,
+    action_required: Option<&'static str>,
+) -> Result<Response, (StatusCode, String)> {
+    let mfa_passed = auth.amr.iter().any(|m| m == "mfa");
+    let session_id = session::create(&state.db, user_id, client, mfa_passed, device.suspicious())
+        .await
+        .map_err(internal_error)?;
+    if device.suspicious() {
+        notify_suspicious_login(state, user_id, session_id, client, device).await?;
+    }
+
+    let mut res = if state.security.bff_mode {
+        let session = bff::create(
+            &state.db, &state.jwt, user_id, role, &auth, client, session_id,
+        )
+        .await
+        .map_err(internal_error)?;
+        bff_response(session, action_required, state)
+    } else {
+        let access = state
+            .jwt
+            .issue_access(&user_id.to_string(), Some(role.to_string()), &auth)
+            .map_err(internal_error)?;
+        let (refresh_token, refresh_hash) = generate_refresh_token();
+        let refresh_expires_at = store_refresh_token(
+            state,
+            user_id,
+            &refresh_hash,
+            client.user_agent.clone(),
+            client.ip.clone(),
+            &SessionLineage::new(&auth, session_id),
+        )
+        .await?;
+        token_response(
+            access,
+            refresh_token,
+            refresh_expires_at,
+            action_required,
+            state,
+        )
+    };
+    append_cookie(
+        &mut res,
+        state.security.cookie(
+            AuthCookie::Device,
+            client.device_id.clone(),
+            CookieDuration::days(DEVICE_COOKIE_DAYS),
+        ),
+    );
+    Ok(res)
+}
+
+/// Browsers cap cookie lifetimes at 400 days.
+const DEVICE_COOKIE_DAYS: i64 = 400;
+
+async fn notify_suspicious_login(
+    state: &AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    client: &ClientInfo,
+    device: DeviceCheck,
+) -> Result<(), (StatusCode, String)> {
+    let report_token = session::issue_report_token(&state.db, session_id)
+        .await
+        .map_err(internal_error)?;
+    events::record(
+        &state.db,
+        user_id,
+        "new_device_login",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({
+            "session_id": session_id,
+            "new_device": device.new_device,
+            "new_network": device.new_network,
+        }),
+    )
+    .await;
+
+    let email: Option<String> = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal_error)?;
+    if let Some(email) = email {
+        state
+            .mailer
+            .send(
+                &email,
+                "New sign-in to your account",
+                &format!(
+                    "Your account was just signed in to from a {} ({}{}).\n\nIf this was you, there's nothing to do. If it wasn't, secure your account now; this signs out every session and requires a new password:\n\n{}",
+                    if device.new_device { "new device" } else { "new network" },
+                    client.user_agent.as_deref().unwrap_or("unknown browser"),
+                    client
+                        .ip
+                        .as_deref()
+                        .map(|ip| format!(", {ip}"))
+                        .unwrap_or_default(),
+                    state
+                        .mailer
+                        .link(&format!("/security/report?token={report_token}")),
+                ),
+            )
+            .await;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ReportSessionPayload {
+    token: String,
+}
+
+/// Target of the "this wasn't me" link: signs the account out everywhere and
+/// locks password sign-in until the password is reset.
+async fn report_session(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<ReportSessionPayload>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let reported = session::take_reported(&state.db, &payload.token)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired link".into()))?;
+    let user_id = reported.user_id;
+
+    session::revoke_all_for_user(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+    let email: String = sqlx::query_scalar(
+        "UPDATE users SET password_reset_required = true, updated_at = now() WHERE id = $1 RETURNING email",
+    )
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "session_reported",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({
+            "session_id": reported.id,
+            "ip": reported.ip,
+            "user_agent": reported.user_agent,
+        }),
+    )
+    .await;
+
+    let reset_token = create_password_reset(&state, user_id).await?;
+    state
+        .mailer
+        .send(
+            &email,
+            "Reset your password",
+            &format!(
+                "We signed your account out everywhere. Choose a new password to sign in again:\n\n{}",
+                state
+                    .mailer
+                    .link(&format!("/reset-password?token={reset_token}")),
+            ),
+        )
+        .await;
+    Ok("session reported")
+}
+
+/// Body field wins over the cookie so non-browser clients keep working even
+/// when a stale cookie is present.
+fn presented_refresh_token(
+    state: &std::sync::Arc<AppState>,
+    headers: &HeaderMap,
+    from_body: Option<String>,
+) -> Option<String> {
+    from_body
+        .filter(|t| !t.is_empty())
+        .or_else(|| cookie_token(headers, &state.security.refresh_cookie_name))
+        .filter(|t| !t.is_empty())
+}
+
+fn token_response(
+    access: String,
+    refresh: String,
+    refresh_expires_at: OffsetDateTime,
+    action_required: Option<&'static str>,
+    state: &std::sync::Arc<AppState>,
+) -> Response {
+    let csrf_token = csrf::generate_token();
+    let body = if state.security.cookie_only_tokens {
+        Json(TokenResponse {
+            access_token: None,
+            refresh_token: None,
+            csrf_token: Some(csrf_token.clone()),
+            action_required,
+        })
+    } else {
+        Json(TokenResponse {
+            access_token: Some(access.clone()),
+            refresh_token: Some(refresh.clone()),
+            csrf_token: Some(csrf_token.clone()),
+            action_required,
+        })
+    };
     let mut res = body.into_response();
-    attach_cookies(&mut res, state, &access, &refresh);
+    attach_cookies(
+        &mut res,
+        state,
+        &access,
+        &refresh,
+        &csrf_token,
+        refresh_expires_at,
+    );
+    res
+}
+
+/// Hands the CSRF token back to a client holding a session cookie, e.g. a
+/// cross-origin SPA that lost its in-memory copy on reload. The existing
+/// token is returned rather than rotated, so a cross-site request here cannot
+/// invalidate the legitimate client's copy; CORS keeps the body from other
+/// origins.
+async fn csrf_token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let cfg = &state.security;
+    let has_session = cookie_token(&headers, &cfg.refresh_cookie_name).is_some()
+        || (cfg.bff_mode && cookie_token(&headers, &cfg.bff_cookie_name).is_some());
+    if !has_session {
+        return Err((StatusCode::UNAUTHORIZED, "no_session".into()));
+    }
+    let existing = cookie_token(&headers, &cfg.csrf_cookie_name).filter(|t| !t.is_empty());
+    let token = existing.clone().unwrap_or_else(csrf::generate_token);
+    let mut res = Json(serde_json::json!({ "csrf_token": token })).into_response();
+    if existing.is_none() {
+        // Outlives any session it could belong to.
+        let max_age = cfg.session_absolute_timeout;
+        append_cookie(&mut res, cfg.cookie(AuthCookie::Csrf, token, max_age));
+    }
+    Ok(res)
+}
+
+fn bff_response(
+    session: String,
+    action_required: Option<&'static str>,
+    state: &std::sync::Arc<AppState>,
+) -> Response {
+    let cfg = &state.security;
+    let csrf_token = csrf::generate_token();
+    let mut res = Json(TokenResponse {
+        access_token: None,
+        refresh_token: None,
+        csrf_token: Some(csrf_token.clone()),
+        action_required,
+    })
+    .into_response();
+    let max_age = cfg.session_absolute_timeout;
+    append_cookie(
+        &mut res,
+        cfg.cookie(AuthCookie::BffSession, session, max_age),
+    );
+    append_cookie(&mut res, cfg.cookie(AuthCookie::Csrf, csrf_token, max_age));
     res
 }
 
@@ -567,51 +1880,49 @@ fn attach_cookies(
     state: &std::sync::Arc<AppState>,
     access: &str,
     refresh: &str,
+    csrf_token: &str,
+    refresh_expires_at: OffsetDateTime,
 ) {
     let cfg = &state.security;
-    let same_site = cfg.same_site;
-    let access_cookie = Cookie::build((cfg.access_cookie_name.clone(), access.to_string()))
-        .http_only(true)
-        .secure(cfg.secure_cookies)
-        .same_site(same_site)
-        .max_age(CookieDuration::minutes(5))
-        .path("/")
-        .build()
-        .to_string();
-    let refresh_cookie = Cookie::build((cfg.refresh_cookie_name.clone(), refresh.to_string()))
-        .http_only(true)
-        .secure(cfg.secure_cookies)
-        .same_site(same_site)
-        .max_age(CookieDuration::days(REFRESH_TTL_DAYS))
-        .path("/")
-        .build()
-        .to_string();
-    res.headers_mut()
-        .append(SET_COOKIE, access_cookie.parse().unwrap());
-    res.headers_mut()
-        .append(SET_COOKIE, refresh_cookie.parse().unwrap());
-}
-
-fn clear_cookies(res: &mut Response, cfg: &crate::security::config::SecurityConfig) {
-    let same_site = cfg.same_site;
-    let access_cookie = Cookie::build((cfg.access_cookie_name.clone(), ""))
-        .http_only(true)
-        .secure(cfg.secure_cookies)
-        .same_site(same_site)
-        .max_age(CookieDuration::seconds(0))
-        .path("/")
-        .build()
-        .to_string();
-    let refresh_cookie = Cookie::build((cfg.refresh_cookie_name.clone(), ""))
-        .http_only(true)
-        .secure(cfg.secure_cookies)
-        .same_site(same_site)
-        .max_age(CookieDuration::seconds(0))
-        .path("/")
-        .build()
-        .to_string();
-    res.headers_mut()
-        .append(SET_COOKIE, access_cookie.parse().unwrap());
+    let refresh_max_age = refresh_expires_at - OffsetDateTime::now_utc();
+    append_cookie(
+        res,
+        cfg.cookie(
+            AuthCookie::Access,
+            access.to_string(),
+            CookieDuration::minutes(5),
+        ),
+    );
+    append_cookie(
+        res,
+        cfg.cookie(AuthCookie::Refresh, refresh.to_string(), refresh_max_age),
+    );
+    if let Some(stale) = cfg.stale_refresh_cookie() {
+        append_cookie(res, stale);
+    }
+    append_cookie(
+        res,
+        cfg.cookie(AuthCookie::Csrf, csrf_token.to_string(), refresh_max_age),
+    );
+}
+
+pub fn clear_cookies(res: &mut Response, cfg: &crate::security::config::SecurityConfig) {
+    let mut cleared = vec![AuthCookie::Access, AuthCookie::Refresh, AuthCookie::Csrf];
+    if cfg.bff_mode {
+        cleared.push(AuthCookie::BffSession);
+    }
+    for which in cleared {
+        append_cookie(
+            res,
+            cfg.cookie(which, String::new(), CookieDuration::seconds(0)),
+        );
+    }
+    if let Some(stale) = cfg.stale_refresh_cookie() {
+        append_cookie(res, stale);
+    }
+}
+
+fn append_cookie(res: &mut Response, cookie: Cookie<'static>) {
     res.headers_mut()
-        .append(SET_COOKIE, refresh_cookie.parse().unwrap());
+        .append(SET_COOKIE, cookie.to_string().parse().unwrap());
 }
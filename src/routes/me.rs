@@ -0,0 +1,678 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    middleware::{from_fn, from_fn_with_state},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::auth::{
+    claims_user_id, clear_cookies, count_user_failure, email_policy_error, generate_refresh_token,
+    hash_refresh_token, internal_error, is_unique_violation, issue_session, locked_out,
+};
+use crate::domain::profile;
+use crate::domain::user::User;
+use crate::jobs::data_export;
+use crate::middleware::rate_limit::rate_limit_with_config;
+use crate::middleware::step_up::{StepUpPolicy, require_step_up};
+use crate::security::device::{ClientInfo, DeviceCheck};
+use crate::security::jwt::{AuthContext, Claims};
+use crate::security::{events, password, rate_limit, session};
+use crate::state::AppState;
+
+/// Account self-service routes; `auth_middleware` is layered on by the parent
+/// router. Credential changes additionally demand a recent authentication.
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/me/password",
+            post(change_password).layer(from_fn(rate_limit_with_config)),
+        )
+        .route("/me/email", post(change_email))
+        .route("/me/delete", post(request_account_deletion))
+        .route("/me/export", post(request_export))
+        .route_layer(from_fn_with_state(StepUpPolicy::default(), require_step_up))
+        .route("/me/profile", get(get_profile).patch(update_profile))
+        .route("/me/security-activity", get(security_activity))
+}
+
+/// Targets of emailed links; the token in the body is the credential.
+pub fn public_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/email/confirm", post(confirm_email_change))
+        .route("/auth/email/cancel", post(cancel_email_change))
+        .route("/exports/download", get(download_export))
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordPayload {
+    current_password: String,
+    new_password: String,
+}
+
+async fn change_password(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<Response, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    // Per account as well as per address: a stolen token must not buy
+    // unlimited guesses at the current password from many addresses.
+    if !rate_limit::check(&format!("password-change:{user_id}"), 5, 60) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate_limited".into()));
+    }
+    let row =
+        sqlx::query("SELECT email, password_hash, role, locked_until FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await
+            .map_err(internal_error)?
+            .ok_or((StatusCode::UNAUTHORIZED, "Unknown user".into()))?;
+    if let Some(until) = row.get::<Option<OffsetDateTime>, _>("locked_until")
+        && until > OffsetDateTime::now_utc()
+    {
+        return Ok(locked_out(until));
+    }
+
+    let email: String = row.get("email");
+    let stored_hash: String = row.get("password_hash");
+    if !password::verify_password(&payload.current_password, &stored_hash)
+        .map_err(internal_error)?
+    {
+        // Wrong guesses here count toward the same lockout as sign-in.
+        return count_user_failure(&state, &client, user_id, &email, "Invalid credentials").await;
+    }
+    if !password::meets_policy(&payload.new_password) {
+        return Err((StatusCode::BAD_REQUEST, password::POLICY_MESSAGE.into()));
+    }
+    if payload.new_password == payload.current_password {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "New password must differ from the current one".into(),
+        ));
+    }
+
+    let new_hash = password::hash_password(&payload.new_password).map_err(internal_error)?;
+    sqlx::query(
+        "UPDATE users SET password_hash = $1, failed_login_count = 0, last_failed_at = NULL, locked_until = NULL, updated_at = now() WHERE id = $2",
+    )
+    .bind(new_hash)
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    // Every session goes, the caller's included; it gets a fresh one below.
+    session::revoke_all_for_user(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "password_changed",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({}),
+    )
+    .await;
+    state
+        .mailer
+        .send(
+            &email,
+            "Your password was changed",
+            &format!(
+                "The password for your account was just changed{}.\n\nIf this wasn't you, reset your password immediately: {}",
+                client
+                    .ip
+                    .as_deref()
+                    .map(|ip| format!(" from {ip}"))
+                    .unwrap_or_default(),
+                state.mailer.link("/forgot-password"),
+            ),
+        )
+        .await;
+
+    let role: String = row.get("role");
+    let auth = AuthContext {
+        auth_time: time::OffsetDateTime::from_unix_timestamp(claims.auth_time)
+            .map_err(internal_error)?,
+        amr: claims.amr.clone(),
+    };
+    issue_session(
+        &state,
+        user_id,
+        &role,
+        auth,
+        &client,
+        DeviceCheck::default(),
+        None,
+    )
+    .await
+}
+
+async fn get_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    load_user(&state, user_id).await.map(Json)
+}
+
+/// Absent fields are left alone; an empty string clears a text field and
+/// `metadata` is replaced wholesale.
+#[derive(Deserialize)]
+struct UpdateProfilePayload {
+    name: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    avatar_url: Option<String>,
+    metadata: Option<serde_json::Value>,
+}
+
+async fn update_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<UpdateProfilePayload>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let bad_request = |msg: &str| (StatusCode::BAD_REQUEST, msg.to_string());
+    // Outer `None`: leave the column alone; inner `None`: clear it.
+    let text_field = |value: Option<String>, validate: fn(&str) -> Result<(), &'static str>| {
+        value
+            .map(|v| non_empty(v).map(|v| validate(&v).map(|_| v)).transpose())
+            .transpose()
+            .map_err(bad_request)
+    };
+    let name = text_field(payload.name, profile::validate_name)?;
+    let locale = text_field(payload.locale, profile::validate_locale)?;
+    let timezone = text_field(payload.timezone, profile::validate_timezone)?;
+    let avatar_url = text_field(payload.avatar_url, profile::validate_avatar_url)?;
+    let metadata = match payload.metadata {
+        None => None,
+        Some(serde_json::Value::Null) => Some(None),
+        Some(metadata) => {
+            profile::validate_metadata(&metadata).map_err(bad_request)?;
+            Some(Some(metadata))
+        }
+    };
+
+    // One statement touching only the supplied columns, so concurrent
+    // updates of different fields do not undo each other.
+    let updated = sqlx::query_as::<_, User>(
+        "UPDATE users SET
+            name = CASE WHEN $1 THEN $2 ELSE name END,
+            locale = CASE WHEN $3 THEN $4 ELSE locale END,
+            timezone = CASE WHEN $5 THEN $6 ELSE timezone END,
+            avatar_url = CASE WHEN $7 THEN $8 ELSE avatar_url END,
+            metadata = CASE WHEN $9 THEN $10 ELSE metadata END,
+            updated_at = now()
+         WHERE id = $11
+         RETURNING *",
+    )
+    .bind(name.is_some())
+    .bind(name.flatten())
+    .bind(locale.is_some())
+    .bind(locale.flatten())
+    .bind(timezone.is_some())
+    .bind(timezone.flatten())
+    .bind(avatar_url.is_some())
+    .bind(avatar_url.flatten())
+    .bind(metadata.is_some())
+    .bind(metadata.flatten())
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, "Unknown user".into()))?;
+    Ok(Json(updated))
+}
+
+async fn load_user(state: &AppState, user_id: Uuid) -> Result<User, (StatusCode, String)> {
+    sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Unknown user".into()))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let trimmed = value.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+/// Schedules the account for erasure after the configured grace period and
+/// signs the user out everywhere. Logging in again before then cancels it.
+async fn request_account_deletion(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+) -> Result<Response, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let scheduled_for = OffsetDateTime::now_utc() + state.security.account_deletion_grace;
+    let email: String = sqlx::query_scalar(
+        "UPDATE users SET deletion_requested_at = now(), deletion_scheduled_for = $1, updated_at = now()
+         WHERE id = $2
+         RETURNING email",
+    )
+    .bind(scheduled_for)
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::NOT_FOUND, "Unknown user".into()))?;
+
+    session::revoke_all_for_user(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "account_deletion_requested",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({ "scheduled_for": scheduled_for.unix_timestamp() }),
+    )
+    .await;
+    state
+        .mailer
+        .send(
+            &email,
+            "Your account is scheduled for deletion",
+            &format!(
+                "Your account and its data will be permanently deleted on {}.\n\nChanged your mind? Just sign in before then to cancel: {}",
+                scheduled_for.date(),
+                state.mailer.link("/login"),
+            ),
+        )
+        .await;
+
+    let mut res = (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "deletion_scheduled_for": scheduled_for.unix_timestamp() })),
+    )
+        .into_response();
+    clear_cookies(&mut res, &state.security);
+    Ok(res)
+}
+
+/// Starts building the caller's data export; the download link is emailed.
+async fn request_export(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let export_id = data_export::start(state, user_id, user_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::CONFLICT, "export_in_progress".into()))?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "export_id": export_id })),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    token: String,
+}
+
+async fn download_export(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DownloadQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let archive = data_export::fetch(&state.db, &query.token)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::GONE, "Invalid or expired link".into()))?;
+    Ok((
+        [(
+            http::header::CONTENT_DISPOSITION,
+            "attachment; filename=\"account-data.json\"",
+        )],
+        Json(archive),
+    )
+        .into_response())
+}
+
+const ACTIVITY_PAGE_DEFAULT: i64 = 20;
+const ACTIVITY_PAGE_MAX: i64 = 100;
+
+#[derive(Deserialize)]
+struct ActivityQuery {
+    /// Opaque cursor from a previous page's `next_before`.
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct ActivityEntry {
+    kind: String,
+    at: i64,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ActivityPage {
+    items: Vec<ActivityEntry>,
+    /// Pass as `before` to fetch the next page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_before: Option<i64>,
+}
+
+/// Newest-first feed of sign-in attempts and security events (password, email
+/// and MFA changes, new devices, ...) for the caller's account.
+async fn security_activity(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<Json<ActivityPage>, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let limit = query
+        .limit
+        .unwrap_or(ACTIVITY_PAGE_DEFAULT)
+        .clamp(1, ACTIVITY_PAGE_MAX);
+    let before = query
+        .before
+        .map(|micros| OffsetDateTime::from_unix_timestamp_nanos(i128::from(micros) * 1000))
+        .transpose()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid cursor".to_string()))?;
+
+    let rows = sqlx::query(
+        "SELECT kind, ip, user_agent, details, created_at FROM (
+             SELECT CASE WHEN success THEN 'login_succeeded' ELSE 'login_failed' END AS kind,
+                    ip, user_agent,
+                    CASE WHEN failure_reason IS NULL THEN '{}'::jsonb
+                         ELSE jsonb_build_object('reason', failure_reason) END AS details,
+                    created_at
+             FROM login_logs WHERE user_id = $1
+             UNION ALL
+             SELECT kind, ip, user_agent, details, created_at
+             FROM security_events WHERE user_id = $1
+         ) activity
+         WHERE $2::timestamptz IS NULL OR created_at < $2
+         ORDER BY created_at DESC
+         LIMIT $3",
+    )
+    .bind(user_id)
+    .bind(before)
+    .bind(limit)
+    .fetch_all(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    // The cursor keeps the database's microsecond precision so entries sharing
+    // a second are not skipped between pages.
+    let next_before = (rows.len() as i64 == limit)
+        .then(|| rows.last())
+        .flatten()
+        .map(|r| {
+            (r.get::<OffsetDateTime, _>("created_at")
+                .unix_timestamp_nanos()
+                / 1000) as i64
+        });
+    let items = rows
+        .into_iter()
+        .map(|r| ActivityEntry {
+            kind: r.get("kind"),
+            at: r.get::<OffsetDateTime, _>("created_at").unix_timestamp(),
+            ip: r.get("ip"),
+            user_agent: r.get("user_agent"),
+            details: r.get("details"),
+        })
+        .collect();
+    Ok(Json(ActivityPage { items, next_before }))
+}
+
+const EMAIL_CHANGE_TTL_HOURS: i64 = 24;
+
+#[derive(Deserialize)]
+struct ChangeEmailPayload {
+    new_email: String,
+}
+
+/// Starts an email change: the new address must confirm before anything is
+/// swapped, and the old address is told and can cancel.
+async fn change_email(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    client: ClientInfo,
+    Json(payload): Json<ChangeEmailPayload>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let user_id = claims_user_id(&claims)?;
+    let new_email = state
+        .email_policy
+        .check(&state.db, payload.new_email.trim())
+        .await
+        .map_err(email_policy_error)?
+        .key();
+    let old_email: String = sqlx::query("SELECT email FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal_error)?
+        .map(|r| r.get("email"))
+        .ok_or((StatusCode::UNAUTHORIZED, "Unknown user".into()))?;
+    // Accounts are unique by `EmailAddress::key`, which is all lowercase.
+    if old_email.to_lowercase() == new_email {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "New email matches the current one".into(),
+        ));
+    }
+    let taken = sqlx::query("SELECT 1 FROM users WHERE lower(email) = $1")
+        .bind(&new_email)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal_error)?
+        .is_some();
+    if taken {
+        return Err((StatusCode::CONFLICT, "email_taken".into()));
+    }
+
+    // Only one pending change per user; a new request supersedes the old one.
+    sqlx::query(
+        "UPDATE email_changes SET cancelled_at = now()
+         WHERE user_id = $1 AND confirmed_at IS NULL AND cancelled_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    let (confirm_token, confirm_hash) = generate_refresh_token();
+    let (cancel_token, cancel_hash) = generate_refresh_token();
+    let expires_at = OffsetDateTime::now_utc() + Duration::hours(EMAIL_CHANGE_TTL_HOURS);
+    sqlx::query(
+        "INSERT INTO email_changes (id, user_id, old_email, new_email, confirm_token_hash, cancel_token_hash, expires_at, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, now())",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(&old_email)
+    .bind(&new_email)
+    .bind(confirm_hash)
+    .bind(cancel_hash)
+    .bind(expires_at)
+    .execute(&state.db)
+    .await
+    .map_err(internal_error)?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "email_change_requested",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({ "new_email": new_email }),
+    )
+    .await;
+
+    state
+        .mailer
+        .send(
+            &new_email,
+            "Confirm your new email address",
+            &format!(
+                "Confirm this address for your account within {EMAIL_CHANGE_TTL_HOURS} hours: {}",
+                state.mailer.link(&format!(
+                    "/confirm-email?token={}",
+                    urlencoding::encode(&confirm_token)
+                )),
+            ),
+        )
+        .await;
+    state
+        .mailer
+        .send(
+            &old_email,
+            "Your account email is being changed",
+            &format!(
+                "A change of your account email to {new_email} was requested. Nothing changes until the new address is confirmed.\n\nIf this wasn't you, cancel it and change your password: {}",
+                state.mailer.link(&format!(
+                    "/cancel-email-change?token={}",
+                    urlencoding::encode(&cancel_token)
+                )),
+            ),
+        )
+        .await;
+
+    Ok("confirmation sent")
+}
+
+#[derive(Deserialize)]
+struct EmailTokenPayload {
+    token: String,
+}
+
+async fn confirm_email_change(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<EmailTokenPayload>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let mut tx = state.db.begin().await.map_err(internal_error)?;
+    let row = sqlx::query(
+        "SELECT id, user_id, old_email, new_email, expires_at FROM email_changes
+         WHERE confirm_token_hash = $1 AND confirmed_at IS NULL AND cancelled_at IS NULL
+         FOR UPDATE",
+    )
+    .bind(hash_refresh_token(&payload.token))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+    let expires_at: OffsetDateTime = row.get("expires_at");
+    if expires_at < OffsetDateTime::now_utc() {
+        return Err((StatusCode::UNAUTHORIZED, "Token expired".into()));
+    }
+    let change_id: Uuid = row.get("id");
+    let user_id: Uuid = row.get("user_id");
+    let old_email: String = row.get("old_email");
+    let new_email: String = row.get("new_email");
+
+    // The address may have been claimed since the request was made.
+    let swapped = sqlx::query("UPDATE users SET email = $1, updated_at = now() WHERE id = $2")
+        .bind(&new_email)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await;
+    if let Err(e) = swapped {
+        return Err(if is_unique_violation(&e) {
+            (StatusCode::CONFLICT, "email_taken".into())
+        } else {
+            internal_error(e)
+        });
+    }
+    sqlx::query("UPDATE email_changes SET confirmed_at = now() WHERE id = $1")
+        .bind(change_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    session::revoke_all_for_user(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+
+    events::record(
+        &state.db,
+        user_id,
+        "email_changed",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({ "old_email": old_email, "new_email": new_email }),
+    )
+    .await;
+    state
+        .mailer
+        .send(
+            &old_email,
+            "Your account email was changed",
+            &format!(
+                "Your account email is now {new_email} and all sessions were signed out.\n\nIf this wasn't you, contact support immediately."
+            ),
+        )
+        .await;
+
+    Ok("email changed")
+}
+
+async fn cancel_email_change(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<EmailTokenPayload>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let row = sqlx::query(
+        "SELECT id, user_id, confirmed_at, cancelled_at FROM email_changes WHERE cancel_token_hash = $1",
+    )
+    .bind(hash_refresh_token(&payload.token))
+    .fetch_optional(&state.db)
+    .await
+    .map_err(internal_error)?
+    .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".into()))?;
+    let confirmed_at: Option<OffsetDateTime> = row.get("confirmed_at");
+    let cancelled_at: Option<OffsetDateTime> = row.get("cancelled_at");
+    if confirmed_at.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "email_change_already_confirmed".into(),
+        ));
+    }
+    if cancelled_at.is_some() {
+        return Ok("email change cancelled");
+    }
+    let user_id: Uuid = row.get("user_id");
+    sqlx::query("UPDATE email_changes SET cancelled_at = now() WHERE id = $1")
+        .bind(row.get::<Uuid, _>("id"))
+        .execute(&state.db)
+        .await
+        .map_err(internal_error)?;
+    events::record(
+        &state.db,
+        user_id,
+        "email_change_cancelled",
+        client.ip.as_deref(),
+        client.user_agent.as_deref(),
+        serde_json::json!({}),
+    )
+    .await;
+    Ok("email change cancelled")
+}
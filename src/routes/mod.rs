@@ -1,25 +1,59 @@
+use crate::middleware::step_up::StepUpPolicy;
 use crate::security::jwt::Claims;
 use crate::{middleware, state::AppState};
 use axum::Json;
-use axum::{Router, middleware::from_fn, routing::get};
+use axum::{
+    Router,
+    middleware::{from_fn, from_fn_with_state},
+    routing::get,
+};
 use std::sync::Arc;
 
 mod admin;
-mod auth;
+pub mod auth;
+mod me;
 
 pub fn router() -> Router<Arc<AppState>> {
     let auth_layer = from_fn(middleware::auth::auth_middleware);
     let admin_layer = from_fn(middleware::admin::admin_only);
     let rate_layer = from_fn(middleware::rate_limit::rate_limit_with_config);
+    let csrf_layer = from_fn(middleware::csrf::csrf_protect);
+    let mfa_layer = from_fn(middleware::mfa_policy::require_mfa_for_role);
+
+    let step_up_layer = from_fn_with_state(
+        StepUpPolicy::default(),
+        middleware::step_up::require_step_up,
+    );
 
     Router::new()
-        .merge(auth::router().layer(rate_layer))
-        .route("/me", get(me).layer(auth_layer.clone()))
-        .route("/dashboard", get(me).layer(auth_layer.clone()))
+        .merge(auth::router().layer(rate_layer.clone()))
+        .merge(me::public_router().layer(rate_layer.clone()))
+        .merge(
+            auth::protected_router()
+                .layer(auth_layer.clone())
+                .layer(rate_layer),
+        )
+        .merge(
+            me::router()
+                .layer(mfa_layer.clone())
+                .layer(auth_layer.clone()),
+        )
+        .route(
+            "/me",
+            get(me).layer(mfa_layer.clone()).layer(auth_layer.clone()),
+        )
+        .route(
+            "/dashboard",
+            get(me).layer(mfa_layer).layer(auth_layer.clone()),
+        )
         .nest(
             "/admin",
-            admin::router().layer(admin_layer).layer(auth_layer),
+            admin::router()
+                .layer(step_up_layer)
+                .layer(admin_layer)
+                .layer(auth_layer),
         )
+        .layer(csrf_layer)
 }
 
 async fn me(axum::extract::Extension(claims): axum::extract::Extension<Claims>) -> Json<Claims> {
@@ -0,0 +1,159 @@
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::config::SecurityConfig;
+use crate::security::device::ClientInfo;
+use crate::security::jwt::{AuthContext, Claims, JwtError, JwtManager};
+
+/// Access tokens are re-minted this long before they actually expire so a
+/// request never races the expiry.
+const ACCESS_REFRESH_MARGIN_SECS: i64 = 30;
+/// Avoid a write per request: `last_seen_at` is only bumped this often.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+#[derive(Debug, Error)]
+pub enum BffError {
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error(transparent)]
+    Jwt(#[from] JwtError),
+}
+
+fn hash_session_id(raw: &str) -> String {
+    hex::encode(Sha256::digest(raw.as_bytes()))
+}
+
+fn generate_session_id() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let raw = hex::encode(bytes);
+    let hash = hash_session_id(&raw);
+    (raw, hash)
+}
+
+/// Creates a server-side session holding the user's access token and returns
+/// the opaque session id, the only thing the browser ever sees.
+pub async fn create(
+    db: &Db,
+    jwt: &JwtManager,
+    user_id: Uuid,
+    role: &str,
+    auth: &AuthContext,
+    client: &ClientInfo,
+    session_id: Uuid,
+) -> Result<String, BffError> {
+    let access = jwt.issue_access(&user_id.to_string(), Some(role.to_string()), auth)?;
+    let access_expires_at = access_expiry(jwt, &access)?;
+    let (raw, hash) = generate_session_id();
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query(
+        "INSERT INTO bff_sessions (id, session_hash, user_id, role, access_token, access_expires_at, created_at, last_seen_at, user_agent, ip, auth_time, amr, session_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $8, $9, $10, $11, $12)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(&hash)
+    .bind(user_id)
+    .bind(role)
+    .bind(&access)
+    .bind(access_expires_at)
+    .bind(now)
+    .bind(&client.user_agent)
+    .bind(&client.ip)
+    .bind(auth.auth_time)
+    .bind(&auth.amr)
+    .bind(session_id)
+    .execute(db)
+    .await?;
+
+    Ok(raw)
+}
+
+/// Resolves a session cookie to the claims of its access token, transparently
+/// minting a new access token when the stored one is about to expire.
+/// Returns `None` for unknown, idle or expired sessions.
+pub async fn resolve(
+    db: &Db,
+    jwt: &JwtManager,
+    cfg: &SecurityConfig,
+    raw: &str,
+) -> Result<Option<Claims>, BffError> {
+    let hash = hash_session_id(raw);
+    let Some(row) = sqlx::query(
+        "SELECT id, user_id, role, access_token, access_expires_at, created_at, last_seen_at,
+                coalesce(auth_time, created_at) AS auth_time, coalesce(amr, '{}') AS amr
+         FROM bff_sessions WHERE session_hash = $1",
+    )
+    .bind(&hash)
+    .fetch_optional(db)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let id: Uuid = row.get("id");
+    let created_at: OffsetDateTime = row.get("created_at");
+    let last_seen_at: OffsetDateTime = row.get("last_seen_at");
+    let now = OffsetDateTime::now_utc();
+    if now - last_seen_at > cfg.session_idle_timeout
+        || now - created_at > cfg.session_absolute_timeout
+    {
+        sqlx::query("DELETE FROM bff_sessions WHERE id = $1")
+            .bind(id)
+            .execute(db)
+            .await?;
+        return Ok(None);
+    }
+
+    let access_expires_at: OffsetDateTime = row.get("access_expires_at");
+    let stored: String = row.get("access_token");
+    if access_expires_at - Duration::seconds(ACCESS_REFRESH_MARGIN_SECS) > now {
+        if now - last_seen_at > Duration::seconds(TOUCH_INTERVAL_SECS) {
+            sqlx::query("UPDATE bff_sessions SET last_seen_at = $1 WHERE id = $2")
+                .bind(now)
+                .bind(id)
+                .execute(db)
+                .await?;
+        }
+        return Ok(Some(jwt.verify(&stored)?));
+    }
+
+    let user_id: Uuid = row.get("user_id");
+    let role: String = row.get("role");
+    let auth = AuthContext {
+        auth_time: row.get("auth_time"),
+        amr: row.get("amr"),
+    };
+    let access = jwt.issue_access(&user_id.to_string(), Some(role), &auth)?;
+    let claims = jwt.verify(&access)?;
+    sqlx::query(
+        "UPDATE bff_sessions SET access_token = $1, access_expires_at = $2, last_seen_at = $3 WHERE id = $4",
+    )
+    .bind(&access)
+    .bind(OffsetDateTime::from_unix_timestamp(claims.exp).unwrap_or(now))
+    .bind(now)
+    .bind(id)
+    .execute(db)
+    .await?;
+    Ok(Some(claims))
+}
+
+pub async fn destroy(db: &Db, raw: &str) -> Result<(), BffError> {
+    sqlx::query("DELETE FROM bff_sessions WHERE session_hash = $1")
+        .bind(hash_session_id(raw))
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+fn access_expiry(jwt: &JwtManager, access: &str) -> Result<OffsetDateTime, BffError> {
+    let claims = jwt.verify(access)?;
+    Ok(OffsetDateTime::from_unix_timestamp(claims.exp)
+        .unwrap_or_else(|_| OffsetDateTime::now_utc()))
+}
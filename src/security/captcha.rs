@@ -0,0 +1,417 @@
+//! Human-verification challenges, used when the risk engine is unsure enough
+//! to ask for proof rather than refuse outright.
+//!
+//! When a third-party widget is configured, only its tokens are accepted,
+//! checked against the provider's "siteverify" endpoint (hCaptcha, Cloudflare
+//! Turnstile and reCAPTCHA all speak the same protocol). Without one, a
+//! built-in proof-of-work stands in: it needs no third party but only slows
+//! bots down, so it never weakens a configured widget. Proof-of-work
+//! challenges are issued stateless and HMAC-signed; answers are single-use
+//! across all instances (`captcha_spent`).
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+
+use crate::infra::db::Db;
+
+#[derive(Debug, Error)]
+pub enum CaptchaError {
+    #[error("verify request failed: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// What the client sends back: which challenge it answered, and the answer.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptchaAnswer {
+    pub provider: String,
+    pub response: String,
+}
+
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// Name clients use to pick the widget and to label their answer.
+    fn provider(&self) -> &'static str;
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, CaptchaError>;
+}
+
+/// A "siteverify"-style provider: the answer token is POSTed as a form with
+/// our secret, and the JSON reply says whether it was good.
+pub struct SiteVerify {
+    provider: &'static str,
+    verify_url: String,
+    secret: String,
+    http: Client,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+impl SiteVerify {
+    pub const HCAPTCHA_URL: &'static str = "https://api.hcaptcha.com/siteverify";
+    pub const TURNSTILE_URL: &'static str =
+        "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+    pub const RECAPTCHA_URL: &'static str = "https://www.google.com/recaptcha/api/siteverify";
+
+    pub fn new(provider: &'static str, verify_url: String, secret: String) -> Self {
+        Self {
+            provider,
+            verify_url,
+            secret,
+            http: Client::builder()
+                .timeout(std::time::Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for SiteVerify {
+    fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, CaptchaError> {
+        let mut form = vec![("secret", self.secret.as_str()), ("response", response)];
+        if let Some(ip) = remote_ip {
+            form.push(("remoteip", ip));
+        }
+        let reply: SiteVerifyResponse = self
+            .http
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if !reply.success && !reply.error_codes.is_empty() {
+            tracing::debug!("{} rejected answer: {:?}", self.provider, reply.error_codes);
+        }
+        Ok(reply.success)
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Provider name clients use for proof-of-work answers.
+const POW_PROVIDER: &str = "pow";
+
+/// Built-in challenge: find a `solution` such that
+/// `sha256("<challenge>:<solution>")` starts with `difficulty` zero bits, and
+/// answer with `"<challenge>:<solution>"`. Costs a browser a second or so and
+/// a bot farm real money at scale.
+pub struct ProofOfWork {
+    key: Vec<u8>,
+    difficulty: u8,
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PowChallenge {
+    pub challenge: String,
+    pub difficulty: u8,
+}
+
+impl ProofOfWork {
+    pub fn new(key: Vec<u8>, difficulty: u8, ttl: Duration) -> Self {
+        Self {
+            key,
+            difficulty,
+            ttl,
+        }
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(payload.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    pub fn issue(&self) -> PowChallenge {
+        let mut nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+        let expires = (OffsetDateTime::now_utc() + self.ttl).unix_timestamp();
+        let payload = format!("{expires}.{}.{}", hex::encode(nonce), self.difficulty);
+        PowChallenge {
+            challenge: format!("{payload}.{}", self.sign(&payload)),
+            difficulty: self.difficulty,
+        }
+    }
+
+    /// The challenge an answer solves, and when it expires, if the answer is
+    /// a valid solution to a challenge we issued. Whether it was already
+    /// spent is for [`spend`] to say.
+    fn check<'a>(&self, response: &'a str) -> Option<(&'a str, OffsetDateTime)> {
+        let (challenge, _solution) = response.rsplit_once(':')?;
+        let (payload, signature) = challenge.rsplit_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        let mut parts = payload.split('.');
+        let (Some(expires), Some(_nonce), Some(difficulty)) = (
+            parts.next().and_then(|v| v.parse::<i64>().ok()),
+            parts.next(),
+            parts.next().and_then(|v| v.parse::<u8>().ok()),
+        ) else {
+            return None;
+        };
+        let expires = OffsetDateTime::from_unix_timestamp(expires).ok()?;
+        if expires <= OffsetDateTime::now_utc()
+            || leading_zero_bits(&Sha256::digest(response.as_bytes())) < difficulty
+        {
+            return None;
+        }
+        Some((challenge, expires))
+    }
+}
+
+/// Marks a proof-of-work challenge as used. Returns `false` if some instance
+/// already accepted an answer to it.
+async fn spend(db: &Db, challenge: &str, expires: OffsetDateTime) -> Result<bool, sqlx::Error> {
+    sqlx::query("DELETE FROM captcha_spent WHERE expires_at <= now()")
+        .execute(db)
+        .await?;
+    let res = sqlx::query(
+        "INSERT INTO captcha_spent (challenge, expires_at) VALUES ($1, $2)
+         ON CONFLICT (challenge) DO NOTHING",
+    )
+    .bind(challenge)
+    .bind(expires)
+    .execute(db)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u8 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            return bits + byte.leading_zeros() as u8;
+        }
+    }
+    bits
+}
+
+/// The challenge on offer: the third-party widget when one is configured,
+/// otherwise proof-of-work.
+#[derive(Clone)]
+pub struct Captcha {
+    pow: Arc<ProofOfWork>,
+    widget: Option<Arc<dyn CaptchaVerifier>>,
+    /// Public key the client needs to render the widget.
+    site_key: Option<String>,
+}
+
+impl Captcha {
+    pub fn new(pow: ProofOfWork) -> Self {
+        Self {
+            pow: Arc::new(pow),
+            widget: None,
+            site_key: None,
+        }
+    }
+
+    pub fn with_widget(
+        mut self,
+        verifier: impl CaptchaVerifier + 'static,
+        site_key: Option<String>,
+    ) -> Self {
+        self.widget = Some(Arc::new(verifier));
+        self.site_key = site_key;
+        self
+    }
+
+    /// `CAPTCHA_PROVIDER` (`hcaptcha`, `turnstile` or `recaptcha`) with
+    /// `CAPTCHA_SECRET` and `CAPTCHA_SITE_KEY` enable a widget;
+    /// `CAPTCHA_VERIFY_URL` overrides the provider's endpoint (e.g. to point
+    /// at a local stub). `POW_SECRET` must be shared by all instances, and
+    /// `POW_DIFFICULTY` sets the work in bits.
+    pub fn from_env() -> Self {
+        let key = match env("POW_SECRET") {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                warn!("POW_SECRET not set; proof-of-work challenges only verify on this instance");
+                let mut key = vec![0u8; 32];
+                OsRng.fill_bytes(&mut key);
+                key
+            }
+        };
+        let difficulty = env("POW_DIFFICULTY")
+            .and_then(|v| v.parse::<u8>().ok())
+            .filter(|d| (1..=32).contains(d))
+            .unwrap_or(18);
+        let captcha = Self::new(ProofOfWork::new(key, difficulty, Duration::minutes(5)));
+
+        let Some(provider) = env("CAPTCHA_PROVIDER") else {
+            return captcha;
+        };
+        let (name, default_url) = match provider.to_ascii_lowercase().as_str() {
+            "hcaptcha" => ("hcaptcha", SiteVerify::HCAPTCHA_URL),
+            "turnstile" => ("turnstile", SiteVerify::TURNSTILE_URL),
+            "recaptcha" => ("recaptcha", SiteVerify::RECAPTCHA_URL),
+            other => {
+                warn!("unknown CAPTCHA_PROVIDER {other:?}; only proof-of-work is offered");
+                return captcha;
+            }
+        };
+        let Some(secret) = env("CAPTCHA_SECRET") else {
+            warn!("CAPTCHA_PROVIDER set without CAPTCHA_SECRET; only proof-of-work is offered");
+            return captcha;
+        };
+        let url = env("CAPTCHA_VERIFY_URL").unwrap_or_else(|| default_url.to_string());
+        captcha.with_widget(SiteVerify::new(name, url, secret), env("CAPTCHA_SITE_KEY"))
+    }
+
+    /// Whether the answer checks out. Provider outages count as failures.
+    pub async fn verify(&self, db: &Db, answer: &CaptchaAnswer, remote_ip: Option<&str>) -> bool {
+        let Some(widget) = &self.widget else {
+            if answer.provider != POW_PROVIDER {
+                return false;
+            }
+            let Some((challenge, expires)) = self.pow.check(&answer.response) else {
+                return false;
+            };
+            return spend(db, challenge, expires).await.unwrap_or_else(|e| {
+                warn!("failed to record spent proof-of-work: {e}");
+                false
+            });
+        };
+        if widget.provider() != answer.provider {
+            return false;
+        }
+        match widget.verify(&answer.response, remote_ip).await {
+            Ok(ok) => ok,
+            Err(e) => {
+                warn!("{} verification failed: {e}", widget.provider());
+                false
+            }
+        }
+    }
+
+    /// What the client must solve: the widget to render or, without one, a
+    /// fresh proof-of-work challenge.
+    pub fn offer(&self) -> serde_json::Value {
+        match &self.widget {
+            Some(widget) => serde_json::json!({
+                "pow": null,
+                "widget": {
+                    "provider": widget.provider(),
+                    "site_key": self.site_key,
+                },
+            }),
+            None => serde_json::json!({
+                "pow": self.pow.issue(),
+                "widget": null,
+            }),
+        }
+    }
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::routing::post;
+    use axum::{Form, Json, Router};
+    use serde_json::json;
+
+    use super::*;
+
+    /// A local "siteverify" endpoint that accepts `good-token` under secret
+    /// `s3cret`, and answers 500 on `/broken`.
+    async fn stub_siteverify() -> String {
+        let app = Router::new()
+            .route(
+                "/siteverify",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    let ok = form.get("secret").map(String::as_str) == Some("s3cret")
+                        && form.get("response").map(String::as_str) == Some("good-token");
+                    Json(if ok {
+                        json!({ "success": true })
+                    } else {
+                        json!({ "success": false, "error-codes": ["invalid-input-response"] })
+                    })
+                }),
+            )
+            .route(
+                "/broken",
+                post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn siteverify_accepts_a_valid_token() {
+        let base = stub_siteverify().await;
+        let verifier = SiteVerify::new("hcaptcha", format!("{base}/siteverify"), "s3cret".into());
+        assert!(
+            verifier
+                .verify("good-token", Some("198.51.100.7"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn siteverify_rejects_a_bad_token() {
+        let base = stub_siteverify().await;
+        let verifier = SiteVerify::new("turnstile", format!("{base}/siteverify"), "s3cret".into());
+        assert!(!verifier.verify("forged", None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn siteverify_rejects_under_the_wrong_secret() {
+        let base = stub_siteverify().await;
+        let verifier = SiteVerify::new("hcaptcha", format!("{base}/siteverify"), "other".into());
+        assert!(!verifier.verify("good-token", None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn siteverify_outage_is_an_error() {
+        let base = stub_siteverify().await;
+        let verifier = SiteVerify::new("hcaptcha", format!("{base}/broken"), "s3cret".into());
+        assert!(verifier.verify("good-token", None).await.is_err());
+    }
+
+    #[test]
+    fn proof_of_work_checks_signature_and_work() {
+        let pow = ProofOfWork::new(b"key".to_vec(), 4, Duration::minutes(5));
+        let challenge = pow.issue().challenge;
+        let answer = (0u64..)
+            .map(|n| format!("{challenge}:{n}"))
+            .find(|a| leading_zero_bits(&Sha256::digest(a.as_bytes())) >= 4)
+            .unwrap();
+        assert_eq!(pow.check(&answer).map(|(c, _)| c), Some(challenge.as_str()));
+
+        let forged = ProofOfWork::new(b"other".to_vec(), 4, Duration::minutes(5));
+        assert!(forged.check(&answer).is_none());
+    }
+}
@@ -0,0 +1,85 @@
+//! Short-lived, single-use tokens bound to a user: the challenge a passkey
+//! ceremony signs, or a parked login whose credentials were verified but
+//! which must clear one more hurdle (e.g. accepting updated terms) before
+//! tokens are issued.
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::jwt::AuthContext;
+
+const CHALLENGE_TTL_MINUTES: i64 = 10;
+
+pub struct LoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// The authentication the parked login already performed.
+    pub auth: AuthContext,
+}
+
+fn hash_challenge(raw: &str) -> String {
+    hex::encode(Sha256::digest(raw.as_bytes()))
+}
+
+/// Issues a challenge, or parks a login, and returns the raw token to hand
+/// to the client.
+pub async fn create(
+    db: &Db,
+    user_id: Uuid,
+    kind: &str,
+    auth: &AuthContext,
+) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let raw = hex::encode(bytes);
+    sqlx::query(
+        "INSERT INTO login_challenges (id, challenge_hash, user_id, kind, auth_time, amr, expires_at, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, now())",
+    )
+    .bind(Uuid::new_v4())
+    .bind(hash_challenge(&raw))
+    .bind(user_id)
+    .bind(kind)
+    .bind(auth.auth_time)
+    .bind(&auth.amr)
+    .bind(OffsetDateTime::now_utc() + Duration::minutes(CHALLENGE_TTL_MINUTES))
+    .execute(db)
+    .await?;
+    Ok(raw)
+}
+
+/// Looks up a live challenge of the given kind without using it up.
+pub async fn find(db: &Db, raw: &str, kind: &str) -> Result<Option<LoginChallenge>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT id, user_id, auth_time, amr FROM login_challenges
+         WHERE challenge_hash = $1 AND kind = $2 AND consumed_at IS NULL AND expires_at > now()",
+    )
+    .bind(hash_challenge(raw))
+    .bind(kind)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(|r| LoginChallenge {
+        id: r.get("id"),
+        user_id: r.get("user_id"),
+        auth: AuthContext {
+            auth_time: r.get("auth_time"),
+            amr: r.get("amr"),
+        },
+    }))
+}
+
+/// Marks a challenge used; `false` if another request got there first.
+pub async fn consume(db: &Db, id: Uuid) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE login_challenges SET consumed_at = now() WHERE id = $1 AND consumed_at IS NULL",
+    )
+    .bind(id)
+    .execute(db)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
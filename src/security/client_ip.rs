@@ -0,0 +1,236 @@
+//! Client address resolution behind reverse proxies.
+//!
+//! The socket peer is the only address we can trust on its own. Forwarding
+//! headers are consulted only when the peer is a configured trusted proxy, and
+//! then walked right to left (nearest hop first): each entry was appended by
+//! the hop before it, so the first address that is not itself a trusted proxy
+//! is the client. Anything further left was supplied by the client and may be
+//! forged.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::{HeaderMap, StatusCode, request::Parts};
+use ipnet::IpNet;
+
+use crate::state::AppState;
+
+/// The resolved client address, stored in request extensions by
+/// `middleware::client_ip::resolve_client_ip`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<ClientIp>().copied().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "client address unavailable".into(),
+        ))
+    }
+}
+
+impl ClientIp {
+    /// Resolves the address for a request as it enters the service.
+    pub fn from_parts(parts: &Parts) -> Option<Self> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>()?.0.ip();
+        let trusted = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .map(|s| s.security.trusted_proxies.as_slice())
+            .unwrap_or_default();
+        Some(Self(resolve(trusted, peer, &parts.headers)))
+    }
+}
+
+/// Parses a comma-separated list of CIDRs or bare addresses.
+pub fn parse_networks(spec: &str) -> Vec<IpNet> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let parsed = s
+                .parse::<IpNet>()
+                .ok()
+                .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from));
+            if parsed.is_none() {
+                tracing::warn!("ignoring invalid network {s:?}");
+            }
+            parsed
+        })
+        .collect()
+}
+
+pub fn resolve(trusted: &[IpNet], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = canonical(peer);
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    // `Forwarded` (RFC 7239) supersedes the de-facto X-Forwarded-For.
+    let chain = forwarded_chain(headers).unwrap_or_else(|| xff_chain(headers));
+    let mut nearest = peer;
+    for hop in chain.into_iter().rev() {
+        match hop {
+            // A trusted proxy wrote garbage (`unknown`, an obfuscated node):
+            // the last hop we could vouch for is as far as we can see.
+            None => return nearest,
+            Some(ip) if is_trusted(&ip) => nearest = ip,
+            Some(ip) => return ip,
+        }
+    }
+    nearest
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+fn xff_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|entry| parse_node(entry.trim()))
+        .collect()
+}
+
+/// The `for=` nodes of all `Forwarded` elements, in order, or `None` when the
+/// header is absent.
+fn forwarded_chain(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut values = headers.get_all("forwarded").iter().peekable();
+    values.peek()?;
+    let chain = values
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+        })
+        .collect();
+    Some(chain)
+}
+
+/// Accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` and `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok().map(canonical);
+    }
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(canonical(ip));
+    }
+    node.parse::<SocketAddr>().ok().map(|s| canonical(s.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn trusted() -> Vec<IpNet> {
+        parse_networks("10.0.0.0/8, 2001:db8:ffff::/48")
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarding_headers() {
+        let h = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("forwarded", "for=203.0.113.9"),
+        ]);
+        assert_eq!(
+            resolve(&trusted(), ip("198.51.100.4"), &h),
+            ip("198.51.100.4")
+        );
+    }
+
+    #[test]
+    fn spoofed_leftmost_entries_are_skipped() {
+        let h = headers(&[("x-forwarded-for", "6.6.6.6, 203.0.113.9, 10.0.0.2")]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("203.0.113.9"));
+        // Repeated header lines form one list, in order.
+        let h = headers(&[
+            ("x-forwarded-for", "6.6.6.6"),
+            ("x-forwarded-for", "203.0.113.9"),
+        ]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn chain_of_trusted_hops_stops_at_the_last_one_seen() {
+        let h = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("10.0.0.3"));
+        let h = headers(&[("x-forwarded-for", "203.0.113.9, garbage, 10.0.0.2")]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("10.0.0.2"));
+        assert_eq!(
+            resolve(&trusted(), ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_and_accepts_quoted_bracketed_ports() {
+        let h = headers(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            (
+                "forwarded",
+                "for=6.6.6.6, For=\"[2001:db8::17]:4711\";proto=https, for=10.0.0.2:8080",
+            ),
+        ]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("2001:db8::17"));
+        let h = headers(&[(
+            "forwarded",
+            "proto=http;for=\"203.0.113.9:443\";by=10.0.0.1",
+        )]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("203.0.113.9"));
+        let h = headers(&[("forwarded", "for=203.0.113.9, for=unknown")]);
+        assert_eq!(resolve(&trusted(), ip("10.0.0.1"), &h), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_canonicalised() {
+        let h = headers(&[(
+            "x-forwarded-for",
+            "::ffff:203.0.113.9, [::ffff:10.0.0.2]:80",
+        )]);
+        assert_eq!(
+            resolve(&trusted(), ip("::ffff:10.0.0.1"), &h),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            resolve(&trusted(), ip("::ffff:198.51.100.4"), &HeaderMap::new()),
+            ip("198.51.100.4")
+        );
+    }
+
+    #[test]
+    fn parses_networks_and_bare_addresses() {
+        let nets = parse_networks("10.0.0.0/8, 192.0.2.1, nonsense, ,2001:db8::/32");
+        assert_eq!(nets.len(), 3);
+        assert!(nets[1].contains(&ip("192.0.2.1")));
+        assert!(!nets[1].contains(&ip("192.0.2.2")));
+    }
+}
@@ -1,12 +1,113 @@
-use cookie::SameSite;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use cookie::{Cookie, SameSite};
+use ipnet::IpNet;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use time::{Duration, OffsetDateTime};
 use tracing::warn;
 
+use crate::security::client_ip;
+use crate::security::email_policy::DnsOverHttps;
+use crate::security::lockout::LockoutPolicy;
+use crate::security::reputation::{self, Feed};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CookiePrefix {
+    None,
+    Secure,
+    Host,
+    /// Strongest prefix each cookie's attributes allow.
+    Auto,
+}
+
+/// The cookies this service sets; used to look up per-cookie attributes.
+#[derive(Clone, Copy, Debug)]
+pub enum AuthCookie {
+    Access,
+    Refresh,
+    Csrf,
+    BffSession,
+    /// Long-lived device identifier used to recognise returning browsers.
+    Device,
+}
+
 #[derive(Clone)]
 pub struct SecurityConfig {
     pub access_cookie_name: String,
     pub refresh_cookie_name: String,
+    /// Readable (non-HttpOnly) cookie holding the double-submit CSRF token.
+    pub csrf_cookie_name: String,
+    /// Backend-for-frontend mode: tokens stay server-side and the browser only
+    /// holds an opaque session cookie named `bff_cookie_name`.
+    pub bff_mode: bool,
+    pub bff_cookie_name: String,
+    pub device_cookie_name: String,
     pub secure_cookies: bool,
     pub same_site: SameSite,
+    /// Shared parent domain (e.g. `example.com`) for cross-subdomain SSO.
+    /// `None` keeps cookies host-only.
+    pub cookie_domain: Option<String>,
+    pub access_cookie_path: String,
+    pub refresh_cookie_path: String,
+    pub csrf_cookie_path: String,
+    /// CHIPS `Partitioned` attribute, for widgets embedded on third-party sites.
+    pub partitioned_cookies: bool,
+    /// Keep tokens out of JSON bodies and rely solely on the HttpOnly cookies.
+    pub cookie_only_tokens: bool,
+    /// Maximum time between two refreshes before the session is dropped.
+    pub session_idle_timeout: Duration,
+    /// Hard cap on a session's lifetime, carried across refresh rotations.
+    pub session_absolute_timeout: Duration,
+    /// How recent an authentication sensitive operations demand by default.
+    pub step_up_max_age: Duration,
+    /// Roles whose sessions must have passed MFA; users in these roles without
+    /// an enrolled factor are sent through enrollment at login.
+    pub mfa_required_roles: Vec<String>,
+    /// Origins trusted for CORS and for CSRF Origin/Referer checks.
+    pub allowed_origins: Vec<String>,
+    /// Reverse proxies whose forwarding headers are believed. Empty means the
+    /// socket peer is always the client.
+    pub trusted_proxies: Vec<IpNet>,
+    /// How long a deletion request can still be cancelled by logging in
+    /// before the account is purged.
+    pub account_deletion_grace: Duration,
+    /// Total risk score at which a sign-in must pass an extra challenge.
+    pub risk_challenge_score: u32,
+    /// Total risk score at which a sign-in is refused.
+    pub risk_block_score: u32,
+    /// Local IP reputation lists, reloaded every `reputation_refresh`.
+    pub reputation_feeds: Vec<Feed>,
+    pub reputation_refresh: Duration,
+    /// Risk score per reputation category (`tor=100,proxy=40`); categories
+    /// not listed score `reputation_default_weight`.
+    pub reputation_weights: HashMap<String, u32>,
+    pub reputation_default_weight: u32,
+    /// Sign-in backoff per account and per client address.
+    pub user_lockout: LockoutPolicy,
+    pub ip_lockout: LockoutPolicy,
+    /// Key for the hashed sign-in identifiers in `login_logs` and
+    /// `identifier_lockouts`; must be shared by all instances.
+    pub identifier_hash_key: Vec<u8>,
+    /// Registration answers the same whether or not the email is taken, and
+    /// the mailbox owner is told by email instead.
+    pub enumeration_safe_registration: bool,
+    /// Domains of throwaway mailboxes, one per line, reloaded every
+    /// `disposable_domains_refresh`.
+    pub disposable_domains_file: Option<PathBuf>,
+    pub disposable_domains_refresh: Duration,
+    /// Refuse new addresses whose domain cannot receive mail, looked up
+    /// through the DNS-over-HTTPS endpoint `email_mx_resolver_url`.
+    pub email_mx_check: bool,
+    pub email_mx_resolver_url: String,
+    /// Relying-party id passkeys are scoped to (a registrable domain); `None`
+    /// turns passkeys off.
+    pub webauthn_rp_id: Option<String>,
+    pub webauthn_rp_name: String,
+    /// Origins allowed to run passkey ceremonies: `WEBAUTHN_ORIGINS`, else
+    /// `allowed_origins`, else `https://<rp id>`.
+    pub webauthn_origins: Vec<String>,
 }
 
 impl SecurityConfig {
@@ -15,21 +116,393 @@ impl SecurityConfig {
             env_string("ACCESS_COOKIE_NAME").unwrap_or_else(|| "access_token".into());
         let refresh_cookie_name =
             env_string("REFRESH_COOKIE_NAME").unwrap_or_else(|| "refresh_token".into());
+        let csrf_cookie_name =
+            env_string("CSRF_COOKIE_NAME").unwrap_or_else(|| "csrf_token".into());
+        let cookie_domain = env_string("COOKIE_DOMAIN");
+        let access_cookie_path = env_string("ACCESS_COOKIE_PATH").unwrap_or_else(|| "/".into());
+        // The refresh cookie is only ever needed by the refresh and logout endpoints.
+        let refresh_cookie_path =
+            env_string("REFRESH_COOKIE_PATH").unwrap_or_else(|| "/auth".into());
+        let csrf_cookie_path = env_string("CSRF_COOKIE_PATH").unwrap_or_else(|| "/".into());
+        let device_cookie_name =
+            env_string("DEVICE_COOKIE_NAME").unwrap_or_else(|| "device_id".into());
+        let cookie_prefix = env_cookie_prefix().unwrap_or(CookiePrefix::None);
+        let partitioned_cookies = env_bool("COOKIE_PARTITIONED").unwrap_or(false);
+
+        let bff_mode = env_bool("BFF_MODE").unwrap_or(false);
+        let mut bff_cookie_name =
+            env_string("BFF_COOKIE_NAME").unwrap_or_else(|| "__Host-session".into());
+        if !bff_cookie_name.starts_with("__Host-") {
+            warn!("BFF_COOKIE_NAME must carry the __Host- prefix; prefixing it");
+            bff_cookie_name = format!("__Host-{bff_cookie_name}");
+        }
 
         let mut secure_cookies = env_bool("COOKIE_SECURE").unwrap_or(true);
         let same_site = env_same_site().unwrap_or(SameSite::None);
+        let cookie_only_tokens = env_bool("COOKIE_ONLY_TOKENS").unwrap_or(false);
 
         if same_site == SameSite::None && !secure_cookies {
             warn!("SameSite=None requires secure cookies; forcing COOKIE_SECURE=true");
             secure_cookies = true;
         }
+        if bff_mode && !secure_cookies {
+            warn!(
+                "BFF mode uses a __Host- cookie which requires Secure; forcing COOKIE_SECURE=true"
+            );
+            secure_cookies = true;
+        }
+        if partitioned_cookies && !secure_cookies {
+            warn!("Partitioned cookies require Secure; forcing COOKIE_SECURE=true");
+            secure_cookies = true;
+        }
+
+        let access_cookie_name = prefixed_name(
+            &access_cookie_name,
+            cookie_prefix,
+            &access_cookie_path,
+            cookie_domain.as_deref(),
+            secure_cookies,
+        );
+        let refresh_cookie_name = prefixed_name(
+            &refresh_cookie_name,
+            cookie_prefix,
+            &refresh_cookie_path,
+            cookie_domain.as_deref(),
+            secure_cookies,
+        );
+        let csrf_cookie_name = prefixed_name(
+            &csrf_cookie_name,
+            cookie_prefix,
+            &csrf_cookie_path,
+            cookie_domain.as_deref(),
+            secure_cookies,
+        );
+
+        let device_cookie_name = prefixed_name(
+            &device_cookie_name,
+            cookie_prefix,
+            "/",
+            cookie_domain.as_deref(),
+            secure_cookies,
+        );
+
+        let session_absolute_timeout = Duration::days(
+            env_i64("SESSION_ABSOLUTE_TIMEOUT_DAYS")
+                .filter(|v| *v > 0)
+                .unwrap_or(30),
+        );
+        let mut session_idle_timeout = Duration::hours(
+            env_i64("SESSION_IDLE_TIMEOUT_HOURS")
+                .filter(|v| *v > 0)
+                .unwrap_or(7 * 24),
+        );
+        if session_idle_timeout > session_absolute_timeout {
+            warn!(
+                "SESSION_IDLE_TIMEOUT_HOURS exceeds SESSION_ABSOLUTE_TIMEOUT_DAYS; capping idle timeout"
+            );
+            session_idle_timeout = session_absolute_timeout;
+        }
+
+        let step_up_max_age = Duration::minutes(
+            env_i64("STEP_UP_MAX_AGE_MINUTES")
+                .filter(|v| *v > 0)
+                .unwrap_or(10),
+        );
+
+        let mfa_required_roles = env_string("MFA_REQUIRED_ROLES")
+            .unwrap_or_else(|| "admin".into())
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let allowed_origins: Vec<String> = env_string("ALLOWED_ORIGINS")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let trusted_proxies = env_string("TRUSTED_PROXIES")
+            .map(|v| client_ip::parse_networks(&v))
+            .unwrap_or_default();
+
+        let account_deletion_grace = Duration::days(
+            env_i64("ACCOUNT_DELETION_GRACE_DAYS")
+                .filter(|v| *v >= 0)
+                .unwrap_or(30),
+        );
+
+        let risk_block_score = env_i64("RISK_BLOCK_SCORE")
+            .filter(|v| *v > 0)
+            .unwrap_or(100) as u32;
+        let mut risk_challenge_score = env_i64("RISK_CHALLENGE_SCORE")
+            .filter(|v| *v > 0)
+            .unwrap_or(40) as u32;
+        if risk_challenge_score > risk_block_score {
+            warn!("RISK_CHALLENGE_SCORE exceeds RISK_BLOCK_SCORE; lowering it to match");
+            risk_challenge_score = risk_block_score;
+        }
+
+        let reputation_feeds = env_string("REPUTATION_FEEDS")
+            .map(|v| reputation::parse_feeds(&v))
+            .unwrap_or_default();
+        let reputation_refresh = Duration::minutes(
+            env_i64("REPUTATION_REFRESH_MINUTES")
+                .filter(|v| *v > 0)
+                .unwrap_or(60),
+        );
+        let reputation_weights = env_string("REPUTATION_WEIGHTS")
+            .map(|v| {
+                v.split(',')
+                    .filter_map(|pair| {
+                        let (category, weight) = pair.split_once('=')?;
+                        let weight = weight.trim().parse().ok();
+                        if weight.is_none() {
+                            warn!("ignoring REPUTATION_WEIGHTS entry {pair:?}");
+                        }
+                        Some((category.trim().to_ascii_lowercase(), weight?))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let reputation_default_weight = env_i64("REPUTATION_DEFAULT_WEIGHT")
+            .filter(|v| *v >= 0)
+            .unwrap_or(risk_challenge_score as i64) as u32;
+
+        let lockout_base_delay = Duration::seconds(
+            env_i64("LOCKOUT_BASE_SECONDS")
+                .filter(|v| *v > 0)
+                .unwrap_or(60),
+        );
+        let lockout_max_delay = Duration::minutes(
+            env_i64("LOCKOUT_MAX_MINUTES")
+                .filter(|v| *v > 0)
+                .unwrap_or(24 * 60),
+        );
+        let lockout_reset_after = Duration::hours(
+            env_i64("LOCKOUT_RESET_HOURS")
+                .filter(|v| *v > 0)
+                .unwrap_or(24),
+        );
+        let user_lockout = LockoutPolicy {
+            threshold: env_i64("LOCKOUT_USER_THRESHOLD")
+                .filter(|v| *v > 0)
+                .unwrap_or(5),
+            base_delay: lockout_base_delay,
+            max_delay: lockout_max_delay,
+            reset_after: lockout_reset_after,
+        };
+        let ip_lockout = LockoutPolicy {
+            threshold: env_i64("LOCKOUT_IP_THRESHOLD")
+                .filter(|v| *v > 0)
+                .unwrap_or(20),
+            ..user_lockout
+        };
+
+        let identifier_hash_key = match env_string("IDENTIFIER_HASH_SECRET") {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                warn!(
+                    "IDENTIFIER_HASH_SECRET not set; sign-in failures are only counted per instance and until restart"
+                );
+                let mut key = vec![0u8; 32];
+                OsRng.fill_bytes(&mut key);
+                key
+            }
+        };
+
+        let enumeration_safe_registration =
+            env_bool("ENUMERATION_SAFE_REGISTRATION").unwrap_or(false);
+        let disposable_domains_file = env_string("DISPOSABLE_DOMAINS_FILE").map(PathBuf::from);
+        let disposable_domains_refresh = Duration::minutes(
+            env_i64("DISPOSABLE_DOMAINS_REFRESH_MINUTES")
+                .filter(|v| *v > 0)
+                .unwrap_or(60),
+        );
+        let email_mx_check = env_bool("EMAIL_MX_CHECK").unwrap_or(false);
+        let email_mx_resolver_url = env_string("EMAIL_MX_RESOLVER_URL")
+            .unwrap_or_else(|| DnsOverHttps::CLOUDFLARE_URL.into());
+
+        let webauthn_rp_id = env_string("WEBAUTHN_RP_ID");
+        let webauthn_rp_name = env_string("WEBAUTHN_RP_NAME").unwrap_or_else(|| "Tajawal".into());
+        let mut webauthn_origins: Vec<String> = env_string("WEBAUTHN_ORIGINS")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|| allowed_origins.clone());
+        if webauthn_origins.is_empty()
+            && let Some(rp_id) = &webauthn_rp_id
+        {
+            webauthn_origins.push(format!("https://{rp_id}"));
+        }
 
         SecurityConfig {
             access_cookie_name,
             refresh_cookie_name,
+            csrf_cookie_name,
+            bff_mode,
+            bff_cookie_name,
+            device_cookie_name,
             secure_cookies,
             same_site,
+            cookie_domain,
+            access_cookie_path,
+            refresh_cookie_path,
+            csrf_cookie_path,
+            partitioned_cookies,
+            cookie_only_tokens,
+            session_idle_timeout,
+            session_absolute_timeout,
+            step_up_max_age,
+            mfa_required_roles,
+            allowed_origins,
+            trusted_proxies,
+            account_deletion_grace,
+            risk_challenge_score,
+            risk_block_score,
+            reputation_feeds,
+            reputation_refresh,
+            reputation_weights,
+            reputation_default_weight,
+            user_lockout,
+            ip_lockout,
+            identifier_hash_key,
+            enumeration_safe_registration,
+            disposable_domains_file,
+            disposable_domains_refresh,
+            email_mx_check,
+            email_mx_resolver_url,
+            webauthn_rp_id,
+            webauthn_rp_name,
+            webauthn_origins,
+        }
+    }
+
+    /// Expiry for a refresh token issued at `now` in a session that began at
+    /// `session_started_at`: slides with activity but never past the absolute limit.
+    pub fn refresh_expiry(
+        &self,
+        session_started_at: OffsetDateTime,
+        now: OffsetDateTime,
+    ) -> OffsetDateTime {
+        (now + self.session_idle_timeout).min(session_started_at + self.session_absolute_timeout)
+    }
+
+    pub fn role_requires_mfa(&self, role: &str) -> bool {
+        self.mfa_required_roles.iter().any(|r| r == role)
+    }
+
+    /// Builds one of our cookies with its configured name, path, domain and
+    /// flags. An empty value with a zero `max_age` clears it.
+    pub fn cookie(&self, which: AuthCookie, value: String, max_age: Duration) -> Cookie<'static> {
+        let (name, path, http_only) = match which {
+            AuthCookie::Access => (
+                &self.access_cookie_name,
+                self.access_cookie_path.as_str(),
+                true,
+            ),
+            AuthCookie::Refresh => (
+                &self.refresh_cookie_name,
+                self.refresh_cookie_path.as_str(),
+                true,
+            ),
+            // Must stay readable by scripts for the double-submit header.
+            AuthCookie::Csrf => (
+                &self.csrf_cookie_name,
+                self.csrf_cookie_path.as_str(),
+                false,
+            ),
+            AuthCookie::Device => (&self.device_cookie_name, "/", true),
+            AuthCookie::BffSession => {
+                // __Host- cookies must be Secure, have Path=/ and no Domain.
+                return Cookie::build((self.bff_cookie_name.clone(), value))
+                    .http_only(true)
+                    .secure(true)
+                    .same_site(self.same_site)
+                    .partitioned(self.partitioned_cookies)
+                    .max_age(max_age)
+                    .path("/")
+                    .build();
+            }
+        };
+        let mut builder = Cookie::build((name.clone(), value))
+            .http_only(http_only)
+            .secure(self.secure_cookies)
+            .same_site(self.same_site)
+            .partitioned(self.partitioned_cookies)
+            .max_age(max_age)
+            .path(path.to_string());
+        if let Some(domain) = &self.cookie_domain
+            && !name.starts_with("__Host-")
+        {
+            builder = builder.domain(domain.clone());
+        }
+        builder.build()
+    }
+
+    /// Expires a refresh cookie left at `Path=/`, where it was set before it
+    /// moved to `refresh_cookie_path`. Cookies are keyed by path, so setting
+    /// the new one does not replace the old, which would otherwise be sent
+    /// along with every request until it ran out.
+    pub fn stale_refresh_cookie(&self) -> Option<Cookie<'static>> {
+        if self.refresh_cookie_path == "/" {
+            return None;
+        }
+        let mut cookie = self.cookie(AuthCookie::Refresh, String::new(), Duration::seconds(0));
+        cookie.set_path("/");
+        Some(cookie)
+    }
+}
+
+/// Applies the requested `__Host-`/`__Secure-` prefix to a cookie name,
+/// downgrading with a warning when the cookie's attributes cannot satisfy it.
+/// A prefix already present in the configured name counts as a request for it.
+fn prefixed_name(
+    name: &str,
+    requested: CookiePrefix,
+    path: &str,
+    domain: Option<&str>,
+    secure: bool,
+) -> String {
+    let (base, requested) = if let Some(base) = name.strip_prefix("__Host-") {
+        (base, CookiePrefix::Host)
+    } else if let Some(base) = name.strip_prefix("__Secure-") {
+        (base, CookiePrefix::Secure)
+    } else {
+        (name, requested)
+    };
+    let host_ok = secure && path == "/" && domain.is_none();
+
+    let applied = match requested {
+        CookiePrefix::None => CookiePrefix::None,
+        CookiePrefix::Auto if host_ok => CookiePrefix::Host,
+        CookiePrefix::Auto if secure => CookiePrefix::Secure,
+        CookiePrefix::Auto => CookiePrefix::None,
+        CookiePrefix::Host if host_ok => CookiePrefix::Host,
+        CookiePrefix::Host | CookiePrefix::Secure if secure => {
+            if requested == CookiePrefix::Host {
+                warn!(
+                    "cookie {base}: __Host- requires Path=/ and no Domain (path={path}, domain={domain:?}); using __Secure-"
+                );
+            }
+            CookiePrefix::Secure
+        }
+        CookiePrefix::Host | CookiePrefix::Secure => {
+            warn!("cookie {base}: prefixed cookies require Secure; dropping prefix");
+            CookiePrefix::None
         }
+    };
+
+    match applied {
+        CookiePrefix::Host => format!("__Host-{base}"),
+        CookiePrefix::Secure => format!("__Secure-{base}"),
+        CookiePrefix::None | CookiePrefix::Auto => base.to_string(),
     }
 }
 
@@ -46,6 +519,10 @@ fn env_string(key: &str) -> Option<String> {
         .filter(|v| !v.is_empty())
 }
 
+fn env_i64(key: &str) -> Option<i64> {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+}
+
 fn env_bool(key: &str) -> Option<bool> {
     std::env::var(key).ok().and_then(|v| {
         let val = v.trim().to_ascii_lowercase();
@@ -57,6 +534,18 @@ fn env_bool(key: &str) -> Option<bool> {
     })
 }
 
+fn env_cookie_prefix() -> Option<CookiePrefix> {
+    std::env::var("COOKIE_PREFIX")
+        .ok()
+        .and_then(|v| match v.trim().to_ascii_lowercase().as_str() {
+            "none" | "" => Some(CookiePrefix::None),
+            "secure" => Some(CookiePrefix::Secure),
+            "host" => Some(CookiePrefix::Host),
+            "auto" => Some(CookiePrefix::Auto),
+            _ => None,
+        })
+}
+
 fn env_same_site() -> Option<SameSite> {
     std::env::var("COOKIE_SAMESITE").ok().and_then(|v| {
         match v.trim().to_ascii_lowercase().as_str() {
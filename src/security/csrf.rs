@@ -0,0 +1,47 @@
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Constant-time comparison of the submitted header against the cookie value.
+pub fn tokens_match(expected: &str, presented: &str) -> bool {
+    if expected.is_empty() || expected.len() != presented.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(presented.bytes())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Reduces an Origin or Referer value to `scheme://host[:port]` for comparison
+/// against the configured allow-list.
+pub fn origin_of(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    if authority.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}://{}",
+        scheme.to_ascii_lowercase(),
+        authority.to_ascii_lowercase()
+    ))
+}
+
+pub fn origin_allowed(origin: &str, allowed: &[String]) -> bool {
+    let Some(origin) = origin_of(origin) else {
+        return false;
+    };
+    allowed
+        .iter()
+        .filter_map(|a| origin_of(a))
+        .any(|a| a == origin)
+}
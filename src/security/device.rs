@@ -0,0 +1,172 @@
+//! Recognises returning devices from a long-lived device cookie together with
+//! the user agent, and the networks a user usually signs in from.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::extract::FromRequestParts;
+use axum::http::{HeaderMap, StatusCode, request::Parts};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::infra::geoip::GeoInfo;
+use crate::middleware::auth::cookie_token;
+use crate::security::client_ip::ClientIp;
+use crate::state::AppState;
+
+/// Who is on the other end of a request, as far as sessions are concerned.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Raw device cookie value; freshly generated when the browser sent none.
+    pub device_id: String,
+    pub geo: Option<GeoInfo>,
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let app = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .cloned()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "missing state".into()))?;
+        Ok(Self::new(ip, &parts.headers, &app))
+    }
+}
+
+impl ClientInfo {
+    pub fn new(ip: IpAddr, headers: &HeaderMap, state: &AppState) -> Self {
+        let device_id = cookie_token(headers, &state.security.device_cookie_name)
+            .filter(|v| v.len() == 64 && v.chars().all(|c| c.is_ascii_hexdigit()))
+            .unwrap_or_else(generate_device_id);
+        let ip = ip.to_string();
+        Self {
+            geo: state.geoip.lookup(&ip),
+            ip: Some(ip),
+            user_agent: headers
+                .get("user-agent")
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string()),
+            device_id,
+        }
+    }
+
+    pub fn device_hash(&self) -> String {
+        hex::encode(Sha256::digest(self.device_id.as_bytes()))
+    }
+}
+
+fn generate_device_id() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// What was unfamiliar about a sign-in. The default means nothing was.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceCheck {
+    pub new_device: bool,
+    pub new_network: bool,
+}
+
+impl DeviceCheck {
+    /// The verdict for a client nothing is known about: a new device, and a
+    /// new network when the address is known.
+    pub fn first_seen(client: &ClientInfo) -> Self {
+        Self {
+            new_device: true,
+            new_network: client.ip.as_deref().and_then(network_of).is_some(),
+        }
+    }
+
+    pub fn suspicious(&self) -> bool {
+        self.new_device || self.new_network
+    }
+}
+
+/// Coarse network an address belongs to (/24 for IPv4, /48 for IPv6), so a
+/// DHCP lease change on the same connection does not look like a new network.
+pub fn network_of(ip: &str) -> Option<String> {
+    match ip.parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            Some(format!("{a}.{b}.{c}.0/24"))
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            Some(format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2]))
+        }
+    }
+}
+
+/// Compares the client with what the user has signed in from before. A user
+/// with no history yet is judged like an unknown email would be, so the
+/// verdict does not reveal whether the account exists.
+pub async fn assess(
+    db: &Db,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> Result<DeviceCheck, sqlx::Error> {
+    let network = client.ip.as_deref().and_then(network_of);
+    let row: (bool, bool, bool) = sqlx::query_as(
+        "SELECT
+             EXISTS (SELECT 1 FROM known_devices WHERE user_id = $1),
+             EXISTS (SELECT 1 FROM known_devices WHERE user_id = $1 AND device_hash = $2 AND user_agent = $3),
+             EXISTS (SELECT 1 FROM known_devices WHERE user_id = $1 AND network = $4)",
+    )
+    .bind(user_id)
+    .bind(client.device_hash())
+    .bind(client.user_agent.as_deref().unwrap_or_default())
+    .bind(network.as_deref().unwrap_or_default())
+    .fetch_one(db)
+    .await?;
+    let (has_history, device_known, network_known) = row;
+    if !has_history {
+        return Ok(DeviceCheck::first_seen(client));
+    }
+    Ok(DeviceCheck {
+        new_device: !device_known,
+        new_network: network.is_some() && !network_known,
+    })
+}
+
+pub async fn remember(db: &Db, user_id: Uuid, client: &ClientInfo) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO known_devices (id, user_id, device_hash, user_agent, network, first_seen_at, last_seen_at)
+         VALUES ($1, $2, $3, $4, $5, now(), now())
+         ON CONFLICT (user_id, device_hash, user_agent, network) DO UPDATE SET last_seen_at = now()",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(client.device_hash())
+    .bind(client.user_agent.as_deref().unwrap_or_default())
+    .bind(
+        client
+            .ip
+            .as_deref()
+            .and_then(network_of)
+            .unwrap_or_default(),
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Assesses the client and then records it as known for next time.
+pub async fn check_and_remember(
+    db: &Db,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> Result<DeviceCheck, sqlx::Error> {
+    let check = assess(db, user_id, client).await?;
+    remember(db, user_id, client).await?;
+    Ok(check)
+}
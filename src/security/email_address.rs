@@ -0,0 +1,148 @@
+//! Mailbox syntax per RFC 5321 §4.1.2 (the SMTP `Mailbox` production), with
+//! RFC 6531 UTF-8 local parts and IDNA domains.
+//!
+//! Comments, folding whitespace and obsolete forms from RFC 5322 are not
+//! accepted, nor are address literals (`user@[192.0.2.1]`): all are valid on
+//! paper but no real mailbox a person types into a sign-up form uses them.
+
+use std::fmt;
+
+/// Longest address that fits in an SMTP `RCPT TO` path.
+const MAX_ADDRESS_LEN: usize = 254;
+const MAX_LOCAL_LEN: usize = 64;
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmailAddress {
+    /// As written; local parts are case-sensitive in principle.
+    pub local: String,
+    /// Lowercased and in ASCII (punycode) form.
+    pub domain: String,
+}
+
+impl EmailAddress {
+    pub fn parse(input: &str) -> Option<Self> {
+        let (local, domain) = input.rsplit_once('@')?;
+        if !valid_local(local) {
+            return None;
+        }
+        let domain = normalize_domain(domain)?;
+        if !is_mailbox_domain(&domain) || local.len() + 1 + domain.len() > MAX_ADDRESS_LEN {
+            return None;
+        }
+        Some(Self {
+            local: local.to_string(),
+            domain,
+        })
+    }
+
+    /// What accounts are unique by: the address with its local part
+    /// lowercased as well. Local parts may be case-sensitive on paper, but
+    /// no mailbox provider treats them so, and `Jane@` and `jane@` are one
+    /// person.
+    pub fn key(&self) -> String {
+        format!("{}@{}", self.local.to_lowercase(), self.domain)
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.local, self.domain)
+    }
+}
+
+fn valid_local(local: &str) -> bool {
+    if local.is_empty() || local.len() > MAX_LOCAL_LEN {
+        return false;
+    }
+    match local
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(quoted) => valid_quoted(quoted),
+        None => local
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext)),
+    }
+}
+
+/// `atext` from RFC 5322 §3.2.3, widened to non-ASCII by RFC 6531.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || !c.is_ascii()
+}
+
+/// Inside of a quoted-string: printable characters, with `"` and `\` only
+/// as `\`-escapes.
+fn valid_quoted(inner: &str) -> bool {
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if (' '..='~').contains(&escaped) => {}
+                _ => return false,
+            },
+            '"' => return false,
+            c if c.is_ascii() && !(' '..='~').contains(&c) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Lowercased ASCII form of a host name (or a suffix of one, such as a bare
+/// top-level domain), if every label is a valid LDH label.
+pub fn normalize_domain(input: &str) -> Option<String> {
+    let domain = idna::domain_to_ascii(input).ok()?;
+    let valid = !domain.is_empty()
+        && domain.len() <= MAX_DOMAIN_LEN
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    valid.then_some(domain)
+}
+
+/// A fully qualified name: at least two labels, and a top-level label that is
+/// not all digits (so it cannot be a bare IPv4 address).
+fn is_mailbox_domain(domain: &str) -> bool {
+    domain
+        .rsplit_once('.')
+        .is_some_and(|(_, tld)| !tld.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domain_is_normalized_and_local_part_kept() {
+        let a = EmailAddress::parse("Jane.Doe@Example.COM").unwrap();
+        assert_eq!(a.to_string(), "Jane.Doe@example.com");
+        assert_eq!(a.key(), "jane.doe@example.com");
+        let idn = EmailAddress::parse("user@Bücher.example").unwrap();
+        assert_eq!(idn.domain, "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for bad in [
+            "no-at-sign",
+            "@example.com",
+            "a..b@example.com",
+            ".a@example.com",
+            "a@localhost",
+            "a@192.0.2.1",
+            "a@[192.0.2.1]",
+            "a@-example.com",
+            "\"unterminated@example.com",
+        ] {
+            assert!(EmailAddress::parse(bad).is_none(), "{bad}");
+        }
+        assert!(EmailAddress::parse("\"john doe\"@example.com").is_some());
+        assert!(EmailAddress::parse("a+tag@sub.example.co").is_some());
+    }
+}
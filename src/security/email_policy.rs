@@ -0,0 +1,397 @@
+//! Which email domains may be used for new accounts.
+//!
+//! An address must parse ([`EmailAddress`]) and then pass, in order: the
+//! admin-managed `email_domain_rules` (a `deny` refuses the domain, an
+//! `allow` vouches for it and skips the remaining checks), the local list of
+//! disposable-mail domains, and, when enabled, a check that the domain can
+//! receive mail at all. Rules and list entries also cover subdomains.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use thiserror::Error;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::config::SecurityConfig;
+use crate::security::email_address::{self, EmailAddress};
+
+#[derive(Debug, Error)]
+pub enum EmailPolicyError {
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("invalid_email")]
+    Invalid,
+    #[error("disposable_email")]
+    Disposable,
+    #[error("email_domain_blocked")]
+    Denied,
+    #[error("email_domain_unreachable")]
+    NoMailServer,
+}
+
+#[derive(Debug, Error)]
+pub enum DomainRuleError {
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("invalid domain: {0}")]
+    InvalidDomain(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("lookup request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("resolver answered with DNS status {0}")]
+    Status(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainAction {
+    Allow,
+    Deny,
+}
+
+impl DomainAction {
+    fn from_db(action: &str) -> Self {
+        match action {
+            "allow" => DomainAction::Allow,
+            _ => DomainAction::Deny,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            DomainAction::Allow => "allow",
+            DomainAction::Deny => "deny",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainRule {
+    pub id: Uuid,
+    pub domain: String,
+    pub action: DomainAction,
+    pub reason: String,
+    pub created_by: Option<Uuid>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl<'r> FromRow<'r, PgRow> for DomainRule {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            domain: row.try_get("domain")?,
+            action: DomainAction::from_db(&row.try_get::<String, _>("action")?),
+            reason: row.try_get("reason")?,
+            created_by: row.try_get("created_by")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+pub struct NewDomainRule {
+    pub domain: String,
+    pub action: DomainAction,
+    pub reason: String,
+    pub created_by: Option<Uuid>,
+}
+
+/// `mail.example.com`, `example.com`, `com`: every name a rule or list entry
+/// for the domain could be written against.
+fn suffixes(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |d| d.split_once('.').map(|(_, rest)| rest))
+}
+
+pub async fn list_rules(db: &Db) -> Result<Vec<DomainRule>, DomainRuleError> {
+    Ok(sqlx::query_as::<_, DomainRule>(
+        "SELECT id, domain, action, reason, created_by, created_at
+         FROM email_domain_rules ORDER BY created_at DESC",
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+/// Adds a rule, replacing any earlier one for the same domain.
+pub async fn create_rule(db: &Db, rule: NewDomainRule) -> Result<DomainRule, DomainRuleError> {
+    let domain = email_address::normalize_domain(rule.domain.trim().trim_start_matches('@'))
+        .ok_or_else(|| DomainRuleError::InvalidDomain(rule.domain.clone()))?;
+    Ok(sqlx::query_as::<_, DomainRule>(
+        "INSERT INTO email_domain_rules (id, domain, action, reason, created_by, created_at)
+         VALUES ($1, $2, $3, $4, $5, now())
+         ON CONFLICT (domain) DO UPDATE SET action = EXCLUDED.action, reason = EXCLUDED.reason,
+             created_by = EXCLUDED.created_by, created_at = EXCLUDED.created_at
+         RETURNING id, domain, action, reason, created_by, created_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(domain)
+    .bind(rule.action.as_str())
+    .bind(&rule.reason)
+    .bind(rule.created_by)
+    .fetch_one(db)
+    .await?)
+}
+
+/// Returns whether a rule was deleted.
+pub async fn delete_rule(db: &Db, id: Uuid) -> Result<bool, DomainRuleError> {
+    let res = sqlx::query("DELETE FROM email_domain_rules WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// The rule for the most specific matching name; at equal specificity a
+/// deny wins.
+async fn rule_for(db: &Db, domain: &str) -> Result<Option<DomainAction>, sqlx::Error> {
+    let names: Vec<&str> = suffixes(domain).collect();
+    let action: Option<String> = sqlx::query_scalar(
+        "SELECT action FROM email_domain_rules WHERE domain = ANY($1)
+         ORDER BY length(domain) DESC, action = 'deny' DESC LIMIT 1",
+    )
+    .bind(&names)
+    .fetch_optional(db)
+    .await?;
+    Ok(action.as_deref().map(DomainAction::from_db))
+}
+
+static DISPOSABLE: Lazy<RwLock<Arc<HashSet<String>>>> = Lazy::new(Default::default);
+
+/// Re-reads the disposable-domain list (one domain per line, `#` comments)
+/// and swaps it in, returning the number of domains loaded. On a read error
+/// the previous list is kept.
+pub async fn reload_disposable(path: &Path) -> Result<usize, std::io::Error> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let domains: HashSet<String> = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(email_address::normalize_domain)
+        .collect();
+    let count = domains.len();
+    *DISPOSABLE.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(domains);
+    Ok(count)
+}
+
+pub fn is_disposable(domain: &str) -> bool {
+    let list = DISPOSABLE.read().unwrap_or_else(|e| e.into_inner()).clone();
+    suffixes(domain).any(|d| list.contains(d))
+}
+
+#[async_trait]
+pub trait MxResolver: Send + Sync {
+    /// Whether the domain accepts mail: it has a usable MX record or, lacking
+    /// any, an address record (the implicit MX of RFC 5321 §5.1).
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, ResolveError>;
+}
+
+/// Resolves over DNS-over-HTTPS with the JSON API that Cloudflare and Google
+/// both serve, so no system resolver configuration is involved.
+pub struct DnsOverHttps {
+    url: String,
+    http: Client,
+}
+
+#[derive(Deserialize)]
+struct DnsResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DnsAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DnsAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+const NOERROR: u32 = 0;
+const NXDOMAIN: u32 = 3;
+const TYPE_A: u16 = 1;
+const TYPE_MX: u16 = 15;
+const TYPE_AAAA: u16 = 28;
+
+impl DnsOverHttps {
+    pub const CLOUDFLARE_URL: &'static str = "https://cloudflare-dns.com/dns-query";
+
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http: Client::builder()
+                .timeout(std::time::Duration::from_secs(3))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Record data of the given type; empty for names that do not exist.
+    async fn lookup(&self, domain: &str, record_type: u16) -> Result<Vec<String>, ResolveError> {
+        let reply: DnsResponse = self
+            .http
+            .get(&self.url)
+            .query(&[("name", domain), ("type", &record_type.to_string())])
+            .header("accept", "application/dns-json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match reply.status {
+            NOERROR => Ok(reply
+                .answer
+                .into_iter()
+                .filter(|a| a.record_type == record_type)
+                .map(|a| a.data)
+                .collect()),
+            NXDOMAIN => Ok(Vec::new()),
+            other => Err(ResolveError::Status(other)),
+        }
+    }
+}
+
+#[async_trait]
+impl MxResolver for DnsOverHttps {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, ResolveError> {
+        let mx = self.lookup(domain, TYPE_MX).await?;
+        if !mx.is_empty() {
+            // "0 ." is a null MX (RFC 7505): the domain says it takes no mail.
+            return Ok(mx.iter().any(|data| {
+                data.split_whitespace()
+                    .nth(1)
+                    .is_some_and(|host| host != ".")
+            }));
+        }
+        for record_type in [TYPE_A, TYPE_AAAA] {
+            if !self.lookup(domain, record_type).await?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The checks run on addresses offered for a new account or email change.
+#[derive(Clone, Default)]
+pub struct EmailPolicy {
+    resolver: Option<Arc<dyn MxResolver>>,
+}
+
+impl EmailPolicy {
+    pub fn from_config(cfg: &SecurityConfig) -> Self {
+        let policy = Self::default();
+        if cfg.email_mx_check {
+            policy.with_resolver(DnsOverHttps::new(cfg.email_mx_resolver_url.clone()))
+        } else {
+            policy
+        }
+    }
+
+    /// Enables the mail-server check with the given resolver.
+    pub fn with_resolver(mut self, resolver: impl MxResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// The parsed address if it may be used. Resolver failures let the
+    /// address through: a DNS outage should not stop sign-ups.
+    pub async fn check(&self, db: &Db, email: &str) -> Result<EmailAddress, EmailPolicyError> {
+        let address = EmailAddress::parse(email).ok_or(EmailPolicyError::Invalid)?;
+        match rule_for(db, &address.domain).await? {
+            Some(DomainAction::Deny) => return Err(EmailPolicyError::Denied),
+            Some(DomainAction::Allow) => return Ok(address),
+            None => {}
+        }
+        if is_disposable(&address.domain) {
+            return Err(EmailPolicyError::Disposable);
+        }
+        if let Some(resolver) = &self.resolver {
+            match resolver.accepts_mail(&address.domain).await {
+                Ok(true) => {}
+                Ok(false) => return Err(EmailPolicyError::NoMailServer),
+                Err(e) => tracing::warn!("mail server lookup for {} failed: {e}", address.domain),
+            }
+        }
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::extract::Query;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// A local DNS-over-HTTPS JSON endpoint with a few canned zones.
+    async fn stub_resolver() -> DnsOverHttps {
+        let app = Router::new().route(
+            "/dns-query",
+            get(|Query(q): Query<HashMap<String, String>>| async move {
+                let record_type: u16 = q["type"].parse().unwrap();
+                let answer = |data: &str| json!({ "Status": 0, "Answer": [{ "type": record_type, "data": data }] });
+                let empty = json!({ "Status": 0 });
+                let reply: Value = match (q["name"].as_str(), record_type) {
+                    ("nxdomain.test", _) => json!({ "Status": 3 }),
+                    ("servfail.test", _) => json!({ "Status": 2 }),
+                    ("nullmx.test", TYPE_MX) => answer("0 ."),
+                    ("mail.test", TYPE_MX) => answer("10 mx.mail.test."),
+                    ("implicit.test", TYPE_A) => answer("192.0.2.25"),
+                    _ => empty,
+                };
+                Json(reply)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        DnsOverHttps::new(format!("http://{addr}/dns-query"))
+    }
+
+    #[tokio::test]
+    async fn nxdomain_accepts_no_mail() {
+        let resolver = stub_resolver().await;
+        assert!(!resolver.accepts_mail("nxdomain.test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn null_mx_accepts_no_mail() {
+        let resolver = stub_resolver().await;
+        assert!(!resolver.accepts_mail("nullmx.test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn mx_record_accepts_mail() {
+        let resolver = stub_resolver().await;
+        assert!(resolver.accepts_mail("mail.test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn address_record_is_an_implicit_mx() {
+        let resolver = stub_resolver().await;
+        assert!(resolver.accepts_mail("implicit.test").await.unwrap());
+        assert!(!resolver.accepts_mail("empty.test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn resolver_failure_is_an_error() {
+        let resolver = stub_resolver().await;
+        assert!(resolver.accepts_mail("servfail.test").await.is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::device::{ClientInfo, network_of};
+use crate::security::stuffing;
+
+/// Appends to the user's security audit trail (`security_events`). Failures are
+/// logged rather than propagated: auditing must not undo the action it records.
+pub async fn record(
+    db: &Db,
+    user_id: Uuid,
+    kind: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    details: serde_json::Value,
+) {
+    let res = sqlx::query(
+        "INSERT INTO security_events (id, user_id, kind, ip, user_agent, details, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, now())",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(kind)
+    .bind(ip)
+    .bind(user_agent)
+    .bind(details)
+    .execute(db)
+    .await;
+    if let Err(e) = res {
+        tracing::warn!("failed to record security event {kind} for {user_id}: {e}");
+    }
+}
+
+/// Writes one row to `login_logs`. `failure_reason` is `None` for a successful
+/// sign-in; `user_id` is `None` when the email matched no account.
+/// `identifier` (the email tried) is only stored hashed under `key`, for
+/// [`stuffing`](crate::security::stuffing) to count distinct accounts.
+pub async fn login_attempt(
+    db: &Db,
+    key: &[u8],
+    user_id: Option<Uuid>,
+    identifier: &str,
+    client: &ClientInfo,
+    failure_reason: Option<&str>,
+) {
+    let geo = client.geo.clone().unwrap_or_default();
+    let res = sqlx::query(
+        "INSERT INTO login_logs (id, user_id, identifier_hash, ip, network, user_agent, success, failure_reason, country, city, asn, latitude, longitude, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, now())",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(stuffing::identifier_hash(key, identifier))
+    .bind(&client.ip)
+    .bind(client.ip.as_deref().and_then(network_of))
+    .bind(&client.user_agent)
+    .bind(failure_reason.is_none())
+    .bind(failure_reason)
+    .bind(geo.country)
+    .bind(geo.city)
+    .bind(geo.asn)
+    .bind(geo.latitude)
+    .bind(geo.longitude)
+    .execute(db)
+    .await;
+    if let Err(e) = res {
+        tracing::warn!("failed to record login attempt: {e}");
+    }
+}
@@ -0,0 +1,214 @@
+//! Address and network bans.
+//!
+//! Rules live in the `ip_rules` table, each matching either a CIDR range
+//! (IPv4 or IPv6; a bare address is a /32 or /128) or an autonomous system
+//! number, optionally until an expiry. Lookups go through an in-memory
+//! binary radix tree ([`PrefixTrie`]) rebuilt from Postgres on a timer and after every admin
+//! change, so checking a request never touches the database.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use thiserror::Error;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::prefix_trie::PrefixTrie;
+
+#[derive(Debug, Error)]
+pub enum IpRuleError {
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("invalid network: {0}")]
+    InvalidNetwork(String),
+    #[error("a rule needs exactly one of cidr or asn")]
+    MissingTarget,
+    #[error("expiry must be in the future")]
+    AlreadyExpired,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpRule {
+    pub id: Uuid,
+    pub cidr: Option<String>,
+    pub asn: Option<i64>,
+    pub reason: String,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub created_by: Option<Uuid>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl<'r> FromRow<'r, PgRow> for IpRule {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            cidr: row.try_get("cidr")?,
+            asn: row.try_get("asn")?,
+            reason: row.try_get("reason")?,
+            expires_at: row.try_get("expires_at")?,
+            created_by: row.try_get("created_by")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+impl IpRule {
+    fn active(&self, now: OffsetDateTime) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+pub struct NewIpRule {
+    pub cidr: Option<String>,
+    pub asn: Option<i64>,
+    pub reason: String,
+    pub expires_at: Option<OffsetDateTime>,
+    /// `None` for automatic bans.
+    pub created_by: Option<Uuid>,
+}
+
+/// Accepts `10.0.0.0/8`, `2001:db8::/32` or a bare address, and returns the
+/// canonical network form (host bits cleared).
+pub fn normalize_cidr(spec: &str) -> Result<IpNet, IpRuleError> {
+    let spec = spec.trim();
+    spec.parse::<IpNet>()
+        .or_else(|_| spec.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| IpRuleError::InvalidNetwork(spec.to_string()))
+}
+
+pub async fn list(db: &Db) -> Result<Vec<IpRule>, IpRuleError> {
+    Ok(sqlx::query_as::<_, IpRule>(
+        "SELECT id, cidr, asn, reason, expires_at, created_by, created_at
+         FROM ip_rules ORDER BY created_at DESC",
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+pub async fn create(db: &Db, rule: NewIpRule) -> Result<IpRule, IpRuleError> {
+    let cidr = match (&rule.cidr, rule.asn) {
+        (Some(cidr), None) => Some(normalize_cidr(cidr)?.to_string()),
+        (None, Some(_)) => None,
+        _ => return Err(IpRuleError::MissingTarget),
+    };
+    if rule
+        .expires_at
+        .is_some_and(|at| at <= OffsetDateTime::now_utc())
+    {
+        return Err(IpRuleError::AlreadyExpired);
+    }
+    Ok(sqlx::query_as::<_, IpRule>(
+        "INSERT INTO ip_rules (id, cidr, asn, reason, expires_at, created_by, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, now())
+         RETURNING id, cidr, asn, reason, expires_at, created_by, created_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(cidr)
+    .bind(rule.asn)
+    .bind(&rule.reason)
+    .bind(rule.expires_at)
+    .bind(rule.created_by)
+    .fetch_one(db)
+    .await?)
+}
+
+/// Returns whether a rule was deleted.
+pub async fn delete(db: &Db, id: Uuid) -> Result<bool, IpRuleError> {
+    let res = sqlx::query("DELETE FROM ip_rules WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+static INDEX: Lazy<RwLock<Arc<RuleIndex>>> = Lazy::new(Default::default);
+
+/// Rebuilds the index from the active rules and swaps it in.
+pub async fn reload(db: &Db) -> Result<usize, IpRuleError> {
+    let rules = sqlx::query_as::<_, IpRule>(
+        "SELECT id, cidr, asn, reason, expires_at, created_by, created_at
+         FROM ip_rules WHERE expires_at IS NULL OR expires_at > now()",
+    )
+    .fetch_all(db)
+    .await?;
+    let count = rules.len();
+    let index = Arc::new(RuleIndex::build(rules));
+    *INDEX.write().unwrap_or_else(|e| e.into_inner()) = index;
+    Ok(count)
+}
+
+/// The most specific active rule covering the address, else one banning its
+/// network's ASN.
+pub fn matching(ip: IpAddr, asn: Option<i64>) -> Option<IpRule> {
+    let index = INDEX.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let now = OffsetDateTime::now_utc();
+    // Checked from the most specific down, so an expired /32 does not hide
+    // the /16 around it until the next reload.
+    index
+        .nets
+        .covering(ip)
+        .into_iter()
+        .rev()
+        .find(|r| r.active(now))
+        .or_else(|| {
+            asn.and_then(|asn| index.by_asn.get(&asn))
+                .filter(|r| r.active(now))
+        })
+        .map(|r| (**r).clone())
+}
+
+#[derive(Default)]
+struct RuleIndex {
+    nets: PrefixTrie<Arc<IpRule>>,
+    by_asn: HashMap<i64, Arc<IpRule>>,
+}
+
+impl RuleIndex {
+    fn build(rules: Vec<IpRule>) -> Self {
+        let mut index = Self::default();
+        for rule in rules {
+            let rule = Arc::new(rule);
+            if let Some(asn) = rule.asn {
+                match index.by_asn.entry(asn) {
+                    Entry::Occupied(mut slot) => keep_longest(slot.get_mut(), rule),
+                    Entry::Vacant(slot) => {
+                        slot.insert(rule);
+                    }
+                }
+                continue;
+            }
+            let Some(net) = rule.cidr.as_deref().and_then(|c| normalize_cidr(c).ok()) else {
+                tracing::warn!("skipping ip rule {} with unusable cidr", rule.id);
+                continue;
+            };
+            match index.nets.slot(net) {
+                Some(existing) => keep_longest(existing, rule),
+                slot => *slot = Some(rule),
+            }
+        }
+        index
+    }
+}
+
+/// Of two rules for the same target, keep the one that lasts longer.
+fn keep_longest(slot: &mut Arc<IpRule>, candidate: Arc<IpRule>) {
+    let outlives = match (slot.expires_at, candidate.expires_at) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(a), Some(b)) => b > a,
+    };
+    if outlives {
+        *slot = candidate;
+    }
+}
@@ -10,6 +10,45 @@ pub struct Claims {
     pub iat: i64,
     pub role: Option<String>,
     pub jti: String,
+    /// Unix time of the last interactive authentication (OIDC `auth_time`).
+    #[serde(default)]
+    pub auth_time: i64,
+    /// Authentication methods used (RFC 8176 values such as `pwd`, `otp`, `mfa`).
+    #[serde(default)]
+    pub amr: Vec<String>,
+}
+
+impl Claims {
+    pub fn authenticated_within(&self, max_age: Duration) -> bool {
+        OffsetDateTime::now_utc().unix_timestamp() - self.auth_time <= max_age.whole_seconds()
+    }
+
+    pub fn mfa_passed(&self) -> bool {
+        self.amr.iter().any(|m| m == "mfa")
+    }
+}
+
+/// How and when the user last proved their identity; carried into every
+/// access token minted for the session, including after refresh.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub auth_time: OffsetDateTime,
+    pub amr: Vec<String>,
+}
+
+impl AuthContext {
+    pub fn now(methods: &[&str]) -> Self {
+        let mut amr: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+        let has = |m: &str| amr.iter().any(|a| a == m);
+        // Password and code, or a passkey unlocked by PIN or biometric.
+        if (has("pwd") && has("otp")) || (has("hwk") && has("user")) {
+            amr.push("mfa".into());
+        }
+        Self {
+            auth_time: OffsetDateTime::now_utc(),
+            amr,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -37,7 +76,12 @@ impl Default for JwtManager {
 }
 
 impl JwtManager {
-    pub fn issue_access(&self, subject: &str, role: Option<String>) -> Result<String, JwtError> {
+    pub fn issue_access(
+        &self,
+        subject: &str,
+        role: Option<String>,
+        auth: &AuthContext,
+    ) -> Result<String, JwtError> {
         let now = OffsetDateTime::now_utc();
         let claims = Claims {
             sub: subject.to_string(),
@@ -45,6 +89,8 @@ impl JwtManager {
             iat: now.unix_timestamp(),
             role,
             jti: uuid::Uuid::new_v4().to_string(),
+            auth_time: auth.auth_time.unix_timestamp(),
+            amr: auth.amr.clone(),
         };
         encode(
             &Header::new(Algorithm::HS256),
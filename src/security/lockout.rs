@@ -0,0 +1,334 @@
+//! Progressive sign-in lockouts.
+//!
+//! Failed credentials are counted per account (`users.failed_login_count`)
+//! and per client address (`ip_lockouts`). Emails with no account are
+//! counted too (`identifier_lockouts`, by identifier hash) under the account
+//! policy, so they lock exactly when a real account would and the lockout
+//! does not tell which emails are registered. Once a count reaches its policy's
+//! threshold every further failure locks the subject for twice as long as
+//! the last, up to a cap; a quiet period resets the streak. Locked subjects
+//! are refused before their password is checked.
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+
+/// How long an emailed unlock link stays valid.
+const UNLOCK_TOKEN_TTL: Duration = Duration::hours(24);
+
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutPolicy {
+    /// Failures in a streak before the first lock.
+    pub threshold: i64,
+    /// Length of the first lock; each later one doubles it.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// A failure this long after the previous one starts a new streak.
+    pub reset_after: Duration,
+}
+
+impl LockoutPolicy {
+    /// Lock length after the `failures`-th failure of a streak, if any.
+    pub fn delay(&self, failures: i64) -> Option<Duration> {
+        if failures < self.threshold {
+            return None;
+        }
+        let doublings = (failures - self.threshold).min(30) as u32;
+        let factor = 2i32.pow(doublings);
+        Some(
+            self.base_delay
+                .checked_mul(factor)
+                .unwrap_or(self.max_delay)
+                .min(self.max_delay),
+        )
+    }
+}
+
+/// The state of a subject after a failure was counted.
+#[derive(Clone, Copy, Debug)]
+pub struct Lockout {
+    pub failures: i64,
+    pub locked_until: Option<OffsetDateTime>,
+}
+
+impl Lockout {
+    /// Whether this failure is the one that started the lockout streak.
+    pub fn just_locked(&self, policy: &LockoutPolicy) -> bool {
+        self.failures == policy.threshold
+    }
+}
+
+pub async fn record_user_failure(
+    db: &Db,
+    policy: &LockoutPolicy,
+    user_id: Uuid,
+) -> Result<Lockout, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+    let failures: i64 = sqlx::query_scalar(
+        "UPDATE users SET failed_login_count = CASE
+                WHEN last_failed_at IS NULL OR last_failed_at < $2 THEN 1
+                ELSE coalesce(failed_login_count, 0) + 1
+            END,
+            last_failed_at = $3
+         WHERE id = $1
+         RETURNING failed_login_count",
+    )
+    .bind(user_id)
+    .bind(now - policy.reset_after)
+    .bind(now)
+    .fetch_one(db)
+    .await?;
+    let locked_until = policy.delay(failures).map(|d| now + d);
+    if locked_until.is_some() {
+        sqlx::query("UPDATE users SET locked_until = $1 WHERE id = $2")
+            .bind(locked_until)
+            .bind(user_id)
+            .execute(db)
+            .await?;
+    }
+    Ok(Lockout {
+        failures,
+        locked_until,
+    })
+}
+
+pub async fn record_ip_failure(
+    db: &Db,
+    policy: &LockoutPolicy,
+    ip: &str,
+) -> Result<Lockout, sqlx::Error> {
+    record_keyed_failure(db, policy, "ip_lockouts", "ip", ip).await
+}
+
+/// When the address may try again, if it is currently locked out.
+pub async fn ip_locked_until(db: &Db, ip: &str) -> Result<Option<OffsetDateTime>, sqlx::Error> {
+    keyed_locked_until(db, "ip_lockouts", "ip", ip).await
+}
+
+/// Counts a sign-in failure against an email that has no account.
+pub async fn record_identifier_failure(
+    db: &Db,
+    policy: &LockoutPolicy,
+    identifier_hash: &str,
+) -> Result<Lockout, sqlx::Error> {
+    record_keyed_failure(
+        db,
+        policy,
+        "identifier_lockouts",
+        "identifier_hash",
+        identifier_hash,
+    )
+    .await
+}
+
+pub async fn identifier_locked_until(
+    db: &Db,
+    identifier_hash: &str,
+) -> Result<Option<OffsetDateTime>, sqlx::Error> {
+    keyed_locked_until(
+        db,
+        "identifier_lockouts",
+        "identifier_hash",
+        identifier_hash,
+    )
+    .await
+}
+
+/// Failure streaks kept in their own table, one row per `key`. `table` and
+/// `column` are fixed names from this module, never input.
+async fn record_keyed_failure(
+    db: &Db,
+    policy: &LockoutPolicy,
+    table: &str,
+    column: &str,
+    key: &str,
+) -> Result<Lockout, sqlx::Error> {
+    let now = OffsetDateTime::now_utc();
+    let failures: i64 = sqlx::query_scalar(&format!(
+        "INSERT INTO {table} ({column}, failures, last_failed_at) VALUES ($1, 1, $3)
+         ON CONFLICT ({column}) DO UPDATE SET failures = CASE
+                WHEN {table}.last_failed_at < $2 THEN 1
+                ELSE {table}.failures + 1
+            END,
+            last_failed_at = $3
+         RETURNING failures"
+    ))
+    .bind(key)
+    .bind(now - policy.reset_after)
+    .bind(now)
+    .fetch_one(db)
+    .await?;
+    let locked_until = policy.delay(failures).map(|d| now + d);
+    if locked_until.is_some() {
+        sqlx::query(&format!(
+            "UPDATE {table} SET locked_until = $1 WHERE {column} = $2"
+        ))
+        .bind(locked_until)
+        .bind(key)
+        .execute(db)
+        .await?;
+    }
+    Ok(Lockout {
+        failures,
+        locked_until,
+    })
+}
+
+async fn keyed_locked_until(
+    db: &Db,
+    table: &str,
+    column: &str,
+    key: &str,
+) -> Result<Option<OffsetDateTime>, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT locked_until FROM {table} WHERE {column} = $1 AND locked_until > now()"
+    ))
+    .bind(key)
+    .fetch_optional(db)
+    .await
+}
+
+/// Clears the account's failure streak and lock. Returns whether the user
+/// exists.
+pub async fn unlock_user(db: &Db, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE users SET failed_login_count = 0, last_failed_at = NULL, locked_until = NULL WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(db)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Returns whether the address had any failures on record.
+pub async fn unlock_ip(db: &Db, ip: &str) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM ip_lockouts WHERE ip = $1")
+        .bind(ip)
+        .execute(db)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpLockout {
+    pub ip: String,
+    pub failures: i64,
+    #[serde(with = "time::serde::timestamp")]
+    pub locked_until: OffsetDateTime,
+}
+
+pub async fn locked_ips(db: &Db) -> Result<Vec<IpLockout>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT ip, failures, locked_until FROM ip_lockouts
+         WHERE locked_until > now() ORDER BY locked_until DESC",
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| IpLockout {
+            ip: r.get("ip"),
+            failures: r.get("failures"),
+            locked_until: r.get("locked_until"),
+        })
+        .collect())
+}
+
+fn hash_token(raw: &str) -> String {
+    hex::encode(Sha256::digest(raw.as_bytes()))
+}
+
+/// Issues the single-use token behind the "unlock my account" email link,
+/// replacing any earlier one.
+pub async fn issue_unlock_token(db: &Db, user_id: Uuid) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let raw = hex::encode(bytes);
+    sqlx::query(
+        "INSERT INTO account_unlocks (user_id, token_hash, expires_at, used) VALUES ($1, $2, $3, false)
+         ON CONFLICT (user_id) DO UPDATE SET token_hash = EXCLUDED.token_hash, expires_at = EXCLUDED.expires_at, used = false",
+    )
+    .bind(user_id)
+    .bind(hash_token(&raw))
+    .bind(OffsetDateTime::now_utc() + UNLOCK_TOKEN_TTL)
+    .execute(db)
+    .await?;
+    Ok(raw)
+}
+
+/// Spends an unlock token and lifts the account's lock. Returns the user it
+/// belonged to, or `None` for unknown, used or expired tokens.
+pub async fn redeem_unlock_token(db: &Db, raw: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    let user_id: Option<Uuid> = sqlx::query_scalar(
+        "UPDATE account_unlocks SET used = true
+         WHERE token_hash = $1 AND NOT used AND expires_at > now()
+         RETURNING user_id",
+    )
+    .bind(hash_token(raw))
+    .fetch_optional(db)
+    .await?;
+    if let Some(user_id) = user_id {
+        unlock_user(db, user_id).await?;
+    }
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            threshold: 5,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::minutes(15),
+            reset_after: Duration::hours(24),
+        }
+    }
+
+    #[test]
+    fn no_lock_below_threshold() {
+        assert_eq!(policy().delay(0), None);
+        assert_eq!(policy().delay(4), None);
+    }
+
+    #[test]
+    fn delay_doubles_from_the_threshold() {
+        let p = policy();
+        assert_eq!(p.delay(5), Some(Duration::seconds(30)));
+        assert_eq!(p.delay(6), Some(Duration::seconds(60)));
+        assert_eq!(p.delay(7), Some(Duration::seconds(120)));
+        assert_eq!(p.delay(9), Some(Duration::seconds(480)));
+    }
+
+    #[test]
+    fn delay_is_capped_without_overflowing() {
+        let p = policy();
+        assert_eq!(p.delay(10), Some(Duration::minutes(15)));
+        assert_eq!(p.delay(i64::MAX), Some(Duration::minutes(15)));
+        let huge = LockoutPolicy {
+            base_delay: Duration::days(365 * 1000),
+            max_delay: Duration::days(1),
+            ..p
+        };
+        assert_eq!(huge.delay(40), Some(Duration::days(1)));
+    }
+
+    #[test]
+    fn only_the_threshold_failure_starts_a_lockout() {
+        let p = policy();
+        let lock = |failures| Lockout {
+            failures,
+            locked_until: None,
+        };
+        assert!(!lock(4).just_locked(&p));
+        assert!(lock(5).just_locked(&p));
+        assert!(!lock(6).just_locked(&p));
+    }
+}
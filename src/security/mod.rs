@@ -1,6 +1,22 @@
+pub mod bff;
+pub mod captcha;
+pub mod challenge;
+pub mod client_ip;
 pub mod config;
+pub mod csrf;
+pub mod device;
+pub mod email_address;
+pub mod email_policy;
+pub mod events;
+pub mod ip_rules;
 pub mod jwt;
+pub mod lockout;
+pub mod passkey;
 pub mod password;
+pub mod prefix_trie;
 pub mod rate_limit;
+pub mod reputation;
 pub mod risk;
+pub mod session;
+pub mod stuffing;
 pub mod totp;
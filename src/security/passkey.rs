@@ -0,0 +1,696 @@
+//! Passkeys (WebAuthn public-key credentials), used as a step-up factor.
+//!
+//! Registration asks for no attestation, so rather than decoding the CBOR
+//! attestation object the server keeps the public key the browser reports
+//! (`response.publicKey`, a DER SubjectPublicKeyInfo) together with its
+//! algorithm. Both ceremonies are bound to a single-use
+//! [`challenge`](crate::security::challenge) and to this relying party: the
+//! client data must carry our challenge and an allowed origin, and the
+//! authenticator data our relying-party id hash and the user-presence flag.
+//! Assertions must then verify under the stored key, and the signature
+//! counter must grow whenever the authenticator keeps one.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::signature::{
+    ECDSA_P256_SHA256_ASN1, ED25519, RSA_PKCS1_2048_8192_SHA256, UnparsedPublicKey,
+    VerificationAlgorithm,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use spki::{ObjectIdentifier, SubjectPublicKeyInfoRef};
+use sqlx::Row;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::infra::db::Db;
+use crate::security::config::SecurityConfig;
+use crate::security::jwt::AuthContext;
+use crate::security::{challenge, csrf};
+
+/// COSE algorithm identifiers we verify, in order of preference.
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+const ALGORITHMS: [i64; 3] = [ES256, EDDSA, RS256];
+
+const REGISTER_CHALLENGE: &str = "passkey_register";
+const ASSERT_CHALLENGE: &str = "passkey_assert";
+/// How long the browser prompt may stay open, in milliseconds.
+const CEREMONY_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_DATA: u8 = 0x40;
+
+const OID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const OID_P256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const OID_ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+const OID_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+
+#[derive(Debug, Error)]
+pub enum PasskeyError {
+    #[error("db error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("malformed passkey response")]
+    Malformed,
+    #[error("passkey challenge invalid or expired")]
+    Challenge,
+    #[error("passkey origin not allowed")]
+    Origin,
+    #[error("passkey is for another site")]
+    RelyingParty,
+    #[error("passkey user presence missing")]
+    UserNotPresent,
+    #[error("unsupported passkey algorithm")]
+    UnsupportedKey,
+    #[error("invalid passkey signature")]
+    Signature,
+    #[error("passkey signature counter went backwards")]
+    Counter,
+    #[error("unknown passkey")]
+    UnknownCredential,
+    #[error("passkey already registered")]
+    Duplicate,
+}
+
+/// Who the credentials are scoped to: `WEBAUTHN_RP_ID` (a registrable domain
+/// such as `example.com`) and the origins allowed to run the ceremonies.
+#[derive(Clone, Debug)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origins: Vec<String>,
+}
+
+impl RelyingParty {
+    /// `None` when no relying-party id is configured, i.e. passkeys are off.
+    pub fn from_config(cfg: &SecurityConfig) -> Option<Self> {
+        let id = cfg.webauthn_rp_id.clone()?;
+        Some(Self {
+            name: cfg.webauthn_rp_name.clone(),
+            origins: cfg.webauthn_origins.clone(),
+            id,
+        })
+    }
+}
+
+/// `PublicKeyCredential.toJSON()` of a new credential; only the fields a
+/// no-attestation registration needs.
+#[derive(Deserialize)]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub public_key: String,
+    pub public_key_algorithm: i64,
+}
+
+/// `PublicKeyCredential.toJSON()` of an assertion.
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    pub id: String,
+    pub response: AssertionData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionData {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+/// A registered credential's public half.
+#[derive(Clone, Debug)]
+pub struct StoredKey {
+    /// DER SubjectPublicKeyInfo.
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: i64,
+}
+
+/// What a verified assertion proved.
+#[derive(Clone, Copy, Debug)]
+pub struct Assertion {
+    /// The authenticator checked a PIN or biometric, not just a touch.
+    pub user_verified: bool,
+    pub sign_count: u32,
+}
+
+impl Assertion {
+    /// `amr` values (RFC 8176) for the session: a hardware-bound key, plus
+    /// `user` when it was unlocked by the person, which makes it multi-factor.
+    pub fn methods(&self) -> &'static [&'static str] {
+        if self.user_verified {
+            &["hwk", "user"]
+        } else {
+            &["hwk"]
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    credential_id: Option<Vec<u8>>,
+}
+
+/// Options for `navigator.credentials.create()`. Credentials the user
+/// already has are excluded so one authenticator is not registered twice.
+pub async fn registration_options(
+    db: &Db,
+    rp: &RelyingParty,
+    user_id: Uuid,
+    email: &str,
+) -> Result<Value, sqlx::Error> {
+    let challenge = new_challenge(db, user_id, REGISTER_CHALLENGE).await?;
+    let exclude: Vec<Value> = credential_ids(db, user_id)
+        .await?
+        .into_iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect();
+    Ok(json!({
+        "challenge": challenge,
+        "rp": { "id": rp.id, "name": rp.name },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(user_id.as_bytes()),
+            "name": email,
+            "displayName": email,
+        },
+        "pubKeyCredParams": ALGORITHMS
+            .iter()
+            .map(|alg| json!({ "type": "public-key", "alg": alg }))
+            .collect::<Vec<_>>(),
+        "excludeCredentials": exclude,
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "preferred",
+        },
+        "attestation": "none",
+        "timeout": CEREMONY_TIMEOUT_MS,
+    }))
+}
+
+/// Checks a `create()` response against its challenge and stores the key.
+pub async fn register(
+    db: &Db,
+    rp: &RelyingParty,
+    user_id: Uuid,
+    name: Option<&str>,
+    reg: &RegistrationResponse,
+) -> Result<Uuid, PasskeyError> {
+    let client_data = decode(&reg.response.client_data_json)?;
+    let challenge = check_client_data(rp, &client_data, "webauthn.create")?;
+    redeem_challenge(db, &challenge, REGISTER_CHALLENGE, user_id).await?;
+
+    let auth_data = parse_authenticator_data(&decode(&reg.response.authenticator_data)?)?;
+    check_authenticator_data(rp, &auth_data)?;
+    let credential_id = decode(&reg.id)?;
+    if auth_data.credential_id.as_deref() != Some(credential_id.as_slice()) {
+        return Err(PasskeyError::Malformed);
+    }
+    let public_key = decode(&reg.response.public_key)?;
+    key_bytes(&public_key, reg.response.public_key_algorithm)?;
+
+    let id = Uuid::new_v4();
+    let res = sqlx::query(
+        "INSERT INTO passkeys (id, user_id, credential_id, public_key, algorithm, sign_count, name, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+         ON CONFLICT (credential_id) DO NOTHING",
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(URL_SAFE_NO_PAD.encode(&credential_id))
+    .bind(&public_key)
+    .bind(reg.response.public_key_algorithm as i32)
+    .bind(auth_data.sign_count as i64)
+    .bind(name)
+    .execute(db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(PasskeyError::Duplicate);
+    }
+    Ok(id)
+}
+
+/// Options for `navigator.credentials.get()`, or `None` when the user has no
+/// passkey to offer.
+pub async fn assertion_options(
+    db: &Db,
+    rp: &RelyingParty,
+    user_id: Uuid,
+) -> Result<Option<Value>, sqlx::Error> {
+    let ids = credential_ids(db, user_id).await?;
+    if ids.is_empty() {
+        return Ok(None);
+    }
+    let challenge = new_challenge(db, user_id, ASSERT_CHALLENGE).await?;
+    Ok(Some(json!({
+        "challenge": challenge,
+        "rpId": rp.id,
+        "allowCredentials": ids
+            .into_iter()
+            .map(|id| json!({ "type": "public-key", "id": id }))
+            .collect::<Vec<_>>(),
+        "userVerification": "preferred",
+        "timeout": CEREMONY_TIMEOUT_MS,
+    })))
+}
+
+/// Verifies a `get()` response from one of `user_id`'s passkeys and
+/// advances its signature counter.
+pub async fn verify(
+    db: &Db,
+    rp: &RelyingParty,
+    user_id: Uuid,
+    assertion: &AssertionResponse,
+) -> Result<Assertion, PasskeyError> {
+    let client_data = decode(&assertion.response.client_data_json)?;
+    let challenge = check_client_data(rp, &client_data, "webauthn.get")?;
+    redeem_challenge(db, &challenge, ASSERT_CHALLENGE, user_id).await?;
+
+    let credential_id = URL_SAFE_NO_PAD.encode(decode(&assertion.id)?);
+    let row = sqlx::query(
+        "SELECT id, public_key, algorithm, sign_count FROM passkeys
+         WHERE user_id = $1 AND credential_id = $2",
+    )
+    .bind(user_id)
+    .bind(&credential_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or(PasskeyError::UnknownCredential)?;
+    let key = StoredKey {
+        public_key: row.get("public_key"),
+        algorithm: row.get::<i32, _>("algorithm") as i64,
+        sign_count: row.get("sign_count"),
+    };
+    let outcome = check_assertion(
+        rp,
+        &key,
+        &client_data,
+        &decode(&assertion.response.authenticator_data)?,
+        &decode(&assertion.response.signature)?,
+    )?;
+
+    // Conditional on the counter read above, so two racing uses of one
+    // assertion cannot both pass.
+    let res = sqlx::query(
+        "UPDATE passkeys SET sign_count = $1, last_used_at = now()
+         WHERE id = $2 AND sign_count = $3",
+    )
+    .bind(outcome.sign_count as i64)
+    .bind(row.get::<Uuid, _>("id"))
+    .bind(key.sign_count)
+    .execute(db)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(PasskeyError::Counter);
+    }
+    Ok(outcome)
+}
+
+/// The checks on an assertion that need no database: relying party, user
+/// presence, signature and counter. `client_data` must already have passed
+/// [`check_client_data`].
+pub fn check_assertion(
+    rp: &RelyingParty,
+    key: &StoredKey,
+    client_data: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> Result<Assertion, PasskeyError> {
+    let auth_data = parse_authenticator_data(authenticator_data)?;
+    check_authenticator_data(rp, &auth_data)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data));
+    verify_signature(&key.public_key, key.algorithm, &signed, signature)?;
+
+    // Authenticators that keep no counter (most synced passkeys) report 0.
+    let counted = key.sign_count > 0 || auth_data.sign_count > 0;
+    if counted && i64::from(auth_data.sign_count) <= key.sign_count {
+        return Err(PasskeyError::Counter);
+    }
+    Ok(Assertion {
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Checks the ceremony type and origin, and returns the challenge in the
+/// form [`challenge`] stores it.
+pub fn check_client_data(
+    rp: &RelyingParty,
+    client_data: &[u8],
+    kind: &str,
+) -> Result<String, PasskeyError> {
+    let data: ClientData =
+        serde_json::from_slice(client_data).map_err(|_| PasskeyError::Malformed)?;
+    if data.kind != kind {
+        return Err(PasskeyError::Malformed);
+    }
+    if !csrf::origin_allowed(&data.origin, &rp.origins) {
+        return Err(PasskeyError::Origin);
+    }
+    Ok(hex::encode(decode(&data.challenge)?))
+}
+
+fn check_authenticator_data(
+    rp: &RelyingParty,
+    auth_data: &AuthenticatorData,
+) -> Result<(), PasskeyError> {
+    if auth_data.rp_id_hash[..] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(PasskeyError::RelyingParty);
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(PasskeyError::UserNotPresent);
+    }
+    Ok(())
+}
+
+/// `rpIdHash (32) | flags (1) | signCount (4)`, then, when the attested-data
+/// flag is set, `aaguid (16) | credentialIdLength (2) | credentialId | key`.
+fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, PasskeyError> {
+    if bytes.len() < 37 {
+        return Err(PasskeyError::Malformed);
+    }
+    let rp_id_hash: [u8; 32] = bytes[..32]
+        .try_into()
+        .map_err(|_| PasskeyError::Malformed)?;
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+    let credential_id = if flags & FLAG_ATTESTED_DATA != 0 {
+        let rest = bytes.get(53..).ok_or(PasskeyError::Malformed)?;
+        let (len, rest) = rest.split_at_checked(2).ok_or(PasskeyError::Malformed)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        Some(rest.get(..len).ok_or(PasskeyError::Malformed)?.to_vec())
+    } else {
+        None
+    };
+    Ok(AuthenticatorData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        credential_id,
+    })
+}
+
+fn verify_signature(
+    public_key: &[u8],
+    algorithm: i64,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), PasskeyError> {
+    let key = key_bytes(public_key, algorithm)?;
+    let verifier: &'static dyn VerificationAlgorithm = match algorithm {
+        ES256 => &ECDSA_P256_SHA256_ASN1,
+        EDDSA => &ED25519,
+        RS256 => &RSA_PKCS1_2048_8192_SHA256,
+        _ => return Err(PasskeyError::UnsupportedKey),
+    };
+    UnparsedPublicKey::new(verifier, key)
+        .verify(message, signature)
+        .map_err(|_| PasskeyError::Signature)
+}
+
+/// The raw key inside a SubjectPublicKeyInfo, if it is of the kind
+/// `algorithm` says: an uncompressed P-256 point, an Ed25519 key, or a
+/// PKCS#1 RSA key.
+fn key_bytes(spki: &[u8], algorithm: i64) -> Result<&[u8], PasskeyError> {
+    let info = SubjectPublicKeyInfoRef::try_from(spki).map_err(|_| PasskeyError::Malformed)?;
+    let oid = info.algorithm.oid;
+    let matches = match algorithm {
+        ES256 => oid == OID_EC_PUBLIC_KEY && info.algorithm.parameters_oid().ok() == Some(OID_P256),
+        EDDSA => oid == OID_ED25519,
+        RS256 => oid == OID_RSA,
+        _ => false,
+    };
+    if !matches {
+        return Err(PasskeyError::UnsupportedKey);
+    }
+    info.subject_public_key
+        .as_bytes()
+        .ok_or(PasskeyError::Malformed)
+}
+
+/// WebAuthn fields are unpadded base64url, but some clients pad them.
+fn decode(value: &str) -> Result<Vec<u8>, PasskeyError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| PasskeyError::Malformed)
+}
+
+async fn new_challenge(db: &Db, user_id: Uuid, kind: &str) -> Result<String, sqlx::Error> {
+    let raw = challenge::create(db, user_id, kind, &AuthContext::now(&[])).await?;
+    let bytes = hex::decode(&raw).expect("challenge tokens are hex");
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+async fn redeem_challenge(
+    db: &Db,
+    raw: &str,
+    kind: &str,
+    user_id: Uuid,
+) -> Result<(), PasskeyError> {
+    let found = challenge::find(db, raw, kind)
+        .await?
+        .filter(|c| c.user_id == user_id)
+        .ok_or(PasskeyError::Challenge)?;
+    if !challenge::consume(db, found.id).await? {
+        return Err(PasskeyError::Challenge);
+    }
+    Ok(())
+}
+
+async fn credential_ids(db: &Db, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT credential_id FROM passkeys WHERE user_id = $1 ORDER BY created_at")
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+
+    use super::*;
+
+    /// DER prefix of a P-256 SubjectPublicKeyInfo, before the 65-byte point.
+    const P256_SPKI_PREFIX: &str = "3059301306072a8648ce3d020106082a8648ce3d030107034200";
+
+    fn rp() -> RelyingParty {
+        RelyingParty {
+            id: "example.com".into(),
+            name: "Example".into(),
+            origins: vec!["https://example.com".into()],
+        }
+    }
+
+    struct Authenticator {
+        key: EcdsaKeyPair,
+        rng: SystemRandom,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            Self { key, rng }
+        }
+
+        fn stored(&self, sign_count: i64) -> StoredKey {
+            let mut public_key = hex::decode(P256_SPKI_PREFIX).unwrap();
+            public_key.extend_from_slice(self.key.public_key().as_ref());
+            StoredKey {
+                public_key,
+                algorithm: ES256,
+                sign_count,
+            }
+        }
+
+        /// Authenticator data, client data and signature for a `get()`.
+        fn assert(&self, rp_id: &str, flags: u8, count: u32) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&count.to_be_bytes());
+            let client_data = json!({
+                "type": "webauthn.get",
+                "challenge": URL_SAFE_NO_PAD.encode([7u8; 32]),
+                "origin": "https://example.com",
+            })
+            .to_string()
+            .into_bytes();
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature = self.key.sign(&self.rng, &signed).unwrap().as_ref().to_vec();
+            (auth_data, client_data, signature)
+        }
+    }
+
+    #[test]
+    fn verifies_a_signed_assertion() {
+        let authenticator = Authenticator::new();
+        let (auth_data, client_data, sig) =
+            authenticator.assert("example.com", FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 5);
+        let outcome = check_assertion(
+            &rp(),
+            &authenticator.stored(4),
+            &client_data,
+            &auth_data,
+            &sig,
+        )
+        .unwrap();
+        assert!(outcome.user_verified);
+        assert_eq!(outcome.sign_count, 5);
+        assert_eq!(outcome.methods(), ["hwk", "user"]);
+    }
+
+    #[test]
+    fn rejects_a_tampered_client_data() {
+        let authenticator = Authenticator::new();
+        let (auth_data, mut client_data, sig) =
+            authenticator.assert("example.com", FLAG_USER_PRESENT, 0);
+        client_data.push(b' ');
+        let err = check_assertion(
+            &rp(),
+            &authenticator.stored(0),
+            &client_data,
+            &auth_data,
+            &sig,
+        );
+        assert!(matches!(err, Err(PasskeyError::Signature)));
+    }
+
+    #[test]
+    fn rejects_a_key_from_another_authenticator() {
+        let (auth_data, client_data, sig) =
+            Authenticator::new().assert("example.com", FLAG_USER_PRESENT, 0);
+        let other = Authenticator::new().stored(0);
+        let err = check_assertion(&rp(), &other, &client_data, &auth_data, &sig);
+        assert!(matches!(err, Err(PasskeyError::Signature)));
+    }
+
+    #[test]
+    fn rejects_another_relying_party_and_missing_presence() {
+        let authenticator = Authenticator::new();
+        let stored = authenticator.stored(0);
+        let (auth_data, client_data, sig) = authenticator.assert("evil.test", FLAG_USER_PRESENT, 0);
+        let err = check_assertion(&rp(), &stored, &client_data, &auth_data, &sig);
+        assert!(matches!(err, Err(PasskeyError::RelyingParty)));
+        let (auth_data, client_data, sig) = authenticator.assert("example.com", 0, 0);
+        let err = check_assertion(&rp(), &stored, &client_data, &auth_data, &sig);
+        assert!(matches!(err, Err(PasskeyError::UserNotPresent)));
+    }
+
+    #[test]
+    fn counter_must_grow_once_in_use() {
+        let authenticator = Authenticator::new();
+        let (auth_data, client_data, sig) =
+            authenticator.assert("example.com", FLAG_USER_PRESENT, 3);
+        let err = check_assertion(
+            &rp(),
+            &authenticator.stored(3),
+            &client_data,
+            &auth_data,
+            &sig,
+        );
+        assert!(matches!(err, Err(PasskeyError::Counter)));
+        let (auth_data, client_data, sig) =
+            authenticator.assert("example.com", FLAG_USER_PRESENT, 0);
+        assert!(
+            check_assertion(
+                &rp(),
+                &authenticator.stored(0),
+                &client_data,
+                &auth_data,
+                &sig
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn client_data_must_match_ceremony_and_origin() {
+        let data = |kind: &str, origin: &str| {
+            json!({ "type": kind, "challenge": "AAEC", "origin": origin })
+                .to_string()
+                .into_bytes()
+        };
+        assert_eq!(
+            check_client_data(
+                &rp(),
+                &data("webauthn.get", "https://example.com"),
+                "webauthn.get"
+            )
+            .unwrap(),
+            "000102"
+        );
+        assert!(matches!(
+            check_client_data(
+                &rp(),
+                &data("webauthn.create", "https://example.com"),
+                "webauthn.get"
+            ),
+            Err(PasskeyError::Malformed)
+        ));
+        assert!(matches!(
+            check_client_data(
+                &rp(),
+                &data("webauthn.get", "https://evil.test"),
+                "webauthn.get"
+            ),
+            Err(PasskeyError::Origin)
+        ));
+    }
+
+    #[test]
+    fn reads_the_credential_id_of_a_new_credential() {
+        let mut bytes = vec![0u8; 32];
+        bytes.push(FLAG_USER_PRESENT | FLAG_ATTESTED_DATA);
+        bytes.extend_from_slice(&[0, 0, 0, 1]);
+        bytes.extend_from_slice(&[0u8; 16]);
+        bytes.extend_from_slice(&[0, 3, 9, 8, 7, 0xa5]);
+        let parsed = parse_authenticator_data(&bytes).unwrap();
+        assert_eq!(parsed.sign_count, 1);
+        assert_eq!(parsed.credential_id.as_deref(), Some(&[9u8, 8, 7][..]));
+        assert!(parse_authenticator_data(&bytes[..40]).is_err());
+    }
+
+    #[test]
+    fn key_must_match_its_algorithm() {
+        let stored = Authenticator::new().stored(0);
+        assert_eq!(key_bytes(&stored.public_key, ES256).unwrap().len(), 65);
+        assert!(matches!(
+            key_bytes(&stored.public_key, EDDSA),
+            Err(PasskeyError::UnsupportedKey)
+        ));
+        assert!(matches!(
+            key_bytes(b"junk", ES256),
+            Err(PasskeyError::Malformed)
+        ));
+    }
+}
@@ -3,6 +3,7 @@ use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 };
 use once_cell::sync::Lazy;
+use rand::RngCore;
 use rand::rngs::OsRng;
 use thiserror::Error;
 
@@ -11,6 +12,15 @@ static ARGON2: Lazy<Argon2<'static>> = Lazy::new(|| {
     Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
 });
 
+/// Hash of a random password under the live parameters. Sign-ins with no
+/// usable hash are verified against it, so "no such user" costs as much as
+/// "wrong password" and timing does not reveal which emails are registered.
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hash_password(&hex::encode(bytes)).expect("dummy password hash")
+});
+
 #[derive(Debug, Error)]
 pub enum PasswordError {
     #[error("hash error: {0}")]
@@ -19,6 +29,12 @@ pub enum PasswordError {
     Verify,
 }
 
+pub const POLICY_MESSAGE: &str = "Password too weak (min 12 chars)";
+
+pub fn meets_policy(plain: &str) -> bool {
+    plain.len() >= 12
+}
+
 pub fn hash_password(plain: &str) -> Result<String, PasswordError> {
     let salt = SaltString::generate(&mut OsRng);
     ARGON2
@@ -31,3 +47,21 @@ pub fn verify_password(plain: &str, hash: &str) -> Result<bool, PasswordError> {
     let parsed = PasswordHash::new(hash).map_err(|e| PasswordError::Hash(e.to_string()))?;
     Ok(ARGON2.verify_password(plain.as_bytes(), &parsed).is_ok())
 }
+
+/// Constant-work sign-in check: a missing or unparsable stored hash (unknown
+/// user, erased account) still costs a full verification, and fails.
+pub fn verify_or_dummy(plain: &str, hash: Option<&str>) -> bool {
+    if let Some(parsed) = hash.and_then(|h| PasswordHash::new(h).ok()) {
+        return ARGON2.verify_password(plain.as_bytes(), &parsed).is_ok();
+    }
+    if let Ok(dummy) = PasswordHash::new(&DUMMY_HASH) {
+        let _ = ARGON2.verify_password(plain.as_bytes(), &dummy);
+    }
+    false
+}
+
+/// Computes the dummy hash up front so the first unknown-user sign-in is not
+/// the slow one.
+pub fn prepare_dummy_hash() {
+    Lazy::force(&DUMMY_HASH);
+}
@@ -0,0 +1,151 @@
+//! Binary radix tree over IPv4 and IPv6 prefixes, shared by the IP rule index
+//! and the reputation feeds.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+const V4_ROOT: usize = 0;
+const V6_ROOT: usize = 1;
+
+/// Maps networks to values. Nodes live in one vector and refer to their
+/// children by index; IPv4-mapped IPv6 addresses are looked up as IPv4.
+pub struct PrefixTrie<T> {
+    nodes: Vec<Node<T>>,
+}
+
+struct Node<T> {
+    children: [Option<usize>; 2],
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn empty() -> Self {
+        Self {
+            children: [None, None],
+            value: None,
+        }
+    }
+}
+
+impl<T> Default for PrefixTrie<T> {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::empty(), Node::empty()],
+        }
+    }
+}
+
+/// Root node, address bits (in the low `width` bits of the `u128`) and width.
+fn key_of(ip: IpAddr) -> (usize, u128, u8) {
+    match ip {
+        IpAddr::V4(v4) => (V4_ROOT, u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => (V4_ROOT, u32::from(v4) as u128, 32),
+            None => (V6_ROOT, u128::from(v6), 128),
+        },
+    }
+}
+
+fn bit(key: u128, width: u8, depth: u8) -> usize {
+    ((key >> (width - 1 - depth)) & 1) as usize
+}
+
+impl<T> PrefixTrie<T> {
+    /// The value slot for a network, creating the path to it as needed.
+    pub fn slot(&mut self, net: IpNet) -> &mut Option<T> {
+        let (mut node, key, width) = key_of(net.network());
+        // `::ffff:10.0.0.0/104` is stored as `10.0.0.0/8`.
+        let prefix_len = net
+            .prefix_len()
+            .saturating_sub(net.max_prefix_len() - width);
+        for depth in 0..prefix_len {
+            let b = bit(key, width, depth);
+            node = match self.nodes[node].children[b] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(Node::empty());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[b] = Some(child);
+                    child
+                }
+            };
+        }
+        &mut self.nodes[node].value
+    }
+
+    /// Values of every network containing `ip`, least specific first.
+    pub fn covering(&self, ip: IpAddr) -> Vec<&T> {
+        let (mut node, key, width) = key_of(ip);
+        let mut found = Vec::new();
+        for depth in 0..=width {
+            if let Some(value) = &self.nodes[node].value {
+                found.push(value);
+            }
+            if depth == width {
+                break;
+            }
+            match self.nodes[node].children[bit(key, width, depth)] {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(nets: &[&str]) -> PrefixTrie<String> {
+        let mut trie = PrefixTrie::default();
+        for net in nets {
+            *trie.slot(net.parse().unwrap()) = Some(net.to_string());
+        }
+        trie
+    }
+
+    fn covering(trie: &PrefixTrie<String>, ip: &str) -> Vec<String> {
+        trie.covering(ip.parse().unwrap())
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn covering_lists_least_to_most_specific() {
+        let t = trie(&["10.0.0.0/8", "10.1.0.0/16", "10.1.2.3/32", "10.2.0.0/16"]);
+        assert_eq!(
+            covering(&t, "10.1.2.3"),
+            ["10.0.0.0/8", "10.1.0.0/16", "10.1.2.3/32"]
+        );
+        assert_eq!(covering(&t, "10.1.2.4"), ["10.0.0.0/8", "10.1.0.0/16"]);
+        assert_eq!(covering(&t, "10.3.0.1"), ["10.0.0.0/8"]);
+        assert!(covering(&t, "11.0.0.1").is_empty());
+    }
+
+    #[test]
+    fn default_route_covers_its_family_only() {
+        let t = trie(&["0.0.0.0/0", "2001:db8::/32"]);
+        assert_eq!(covering(&t, "198.51.100.1"), ["0.0.0.0/0"]);
+        assert_eq!(covering(&t, "2001:db8:1::1"), ["2001:db8::/32"]);
+        assert!(covering(&t, "2001:db9::1").is_empty());
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_and_networks_are_ipv4() {
+        let t = trie(&["::ffff:192.0.2.0/120", "198.51.100.0/24"]);
+        assert_eq!(covering(&t, "192.0.2.7"), ["::ffff:192.0.2.0/120"]);
+        assert_eq!(covering(&t, "::ffff:198.51.100.9"), ["198.51.100.0/24"]);
+    }
+
+    #[test]
+    fn cleared_slot_is_no_longer_found() {
+        let mut t = trie(&["10.0.0.0/8", "10.1.0.0/16"]);
+        *t.slot("10.1.0.0/16".parse().unwrap()) = None;
+        assert_eq!(covering(&t, "10.1.2.3"), ["10.0.0.0/8"]);
+        *t.slot("10.0.0.0/8".parse().unwrap()) = None;
+        assert!(covering(&t, "10.1.2.3").is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+//! Offline IP reputation from local list files (Tor exit nodes, open proxies,
+//! abusive ranges, ...).
+//!
+//! Each feed is a file tagged with a category. Plain-text feeds hold one
+//! address or CIDR per line, with `#` comments. CSV feeds take the address
+//! from the first column and, when present, a per-row category from the
+//! second. Lines that do not parse (headers, junk) are skipped. The merged
+//! lists are kept in a [`PrefixTrie`] that a background job rebuilds on a
+//! schedule; the risk engine turns the tags into weighted signals.
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+
+use crate::security::prefix_trie::PrefixTrie;
+
+#[derive(Debug, Error)]
+pub enum ReputationError {
+    #[error("failed to read feed {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("feed loader panicked: {0}")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+#[derive(Clone, Debug)]
+pub struct Feed {
+    pub category: String,
+    pub path: PathBuf,
+}
+
+/// Parses `tor=/var/lib/feeds/tor.txt,proxy=/var/lib/feeds/proxies.csv`.
+pub fn parse_feeds(spec: &str) -> Vec<Feed> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry
+                .split_once('=')
+                .map(|(category, path)| (category.trim(), path.trim()))
+                .filter(|(category, path)| !category.is_empty() && !path.is_empty());
+            if parsed.is_none() {
+                tracing::warn!("ignoring reputation feed {entry:?}; expected category=path");
+            }
+            parsed.map(|(category, path)| Feed {
+                category: category.to_ascii_lowercase(),
+                path: PathBuf::from(path),
+            })
+        })
+        .collect()
+}
+
+type TagIndex = PrefixTrie<Vec<Arc<str>>>;
+
+static INDEX: Lazy<RwLock<Arc<TagIndex>>> = Lazy::new(Default::default);
+
+/// Re-reads every feed and swaps in the new index, returning the number of
+/// entries loaded. If any feed cannot be read the previous index is kept:
+/// stale lists beat none.
+pub async fn reload(feeds: &[Feed]) -> Result<usize, ReputationError> {
+    let feeds = feeds.to_vec();
+    let (index, count) = tokio::task::spawn_blocking(move || build(&feeds)).await??;
+    *INDEX.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(index);
+    Ok(count)
+}
+
+/// Every category the address is listed under, in no particular order.
+pub fn tags(ip: IpAddr) -> Vec<Arc<str>> {
+    let index = INDEX.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut tags: Vec<Arc<str>> = index.covering(ip).into_iter().flatten().cloned().collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn build(feeds: &[Feed]) -> Result<(TagIndex, usize), ReputationError> {
+    let mut index = TagIndex::default();
+    let mut count = 0;
+    for feed in feeds {
+        let contents =
+            std::fs::read_to_string(&feed.path).map_err(|source| ReputationError::Io {
+                path: feed.path.clone(),
+                source,
+            })?;
+        let csv = feed
+            .path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        let default_tag: Arc<str> = Arc::from(feed.category.as_str());
+        let mut skipped = 0;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (addr, category) = if csv {
+                let mut fields = line.split(',').map(|f| f.trim().trim_matches('"'));
+                (
+                    fields.next().unwrap_or_default(),
+                    fields.next().filter(|c| !c.is_empty()),
+                )
+            } else {
+                (line.split_whitespace().next().unwrap_or_default(), None)
+            };
+            let Some(net) = parse_entry(addr) else {
+                skipped += 1;
+                continue;
+            };
+            let tag = match category {
+                Some(c) => Arc::from(c.to_ascii_lowercase()),
+                None => default_tag.clone(),
+            };
+            let tags = index.slot(net).get_or_insert_with(Vec::new);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            count += 1;
+        }
+        if skipped > 0 {
+            tracing::debug!(
+                "reputation feed {}: skipped {skipped} unparsable lines",
+                feed.path.display()
+            );
+        }
+    }
+    Ok((index, count))
+}
+
+fn parse_entry(addr: &str) -> Option<IpNet> {
+    addr.parse::<IpNet>()
+        .or_else(|_| addr.parse::<IpAddr>().map(IpNet::from))
+        .ok()
+        .map(|net| net.trunc())
+}
@@ -1,76 +1,546 @@
-use axum::http::HeaderMap;
-use time::OffsetDateTime;
+//! Sign-in risk scoring. Each [`RiskSignal`] inspects the attempt and may
+//! contribute a weighted score; the total maps to allow, challenge or block,
+//! and every decision is stored in `risk_decisions` with its signals.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::Row;
+use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
 use crate::infra::db::Db;
-use sqlx::Row;
+use crate::infra::geoip::GeoInfo;
+use crate::security::captcha::Captcha;
+use crate::security::config::SecurityConfig;
+use crate::security::device::DeviceCheck;
+use crate::security::{ip_rules, reputation, stuffing};
 
+/// Score at which a signal alone blocks the attempt.
+const DECISIVE: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RiskDecision {
     Allow,
+    /// Proceed only after an extra proof (MFA or CAPTCHA).
+    Challenge(&'static str),
     Block(&'static str),
 }
 
-pub async fn risk_check(
-    db: &Db,
-    user_id: Option<Uuid>,
-    ip: Option<&str>,
-    user_agent: Option<&str>,
-) -> RiskDecision {
-    // Banned users
-    if let Some(uid) = user_id {
-        if let Ok(Some(_)) = sqlx::query("SELECT 1 FROM banned_users WHERE user_id = $1")
+impl RiskDecision {
+    fn label(&self) -> &'static str {
+        match self {
+            RiskDecision::Allow => "allow",
+            RiskDecision::Challenge(_) => "challenge",
+            RiskDecision::Block(_) => "block",
+        }
+    }
+}
+
+/// What is known about the attempt being scored.
+pub struct RiskContext<'a> {
+    /// `login`, `register`, `refresh`, ...
+    pub action: &'static str,
+    pub user_id: Option<Uuid>,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub geo: Option<&'a GeoInfo>,
+    /// Device familiarity, when the caller has assessed it.
+    pub device: Option<DeviceCheck>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Signal {
+    pub name: &'static str,
+    pub score: u32,
+    pub detail: serde_json::Value,
+}
+
+impl Signal {
+    pub fn new(name: &'static str, score: u32, detail: serde_json::Value) -> Self {
+        Self {
+            name,
+            score,
+            detail,
+        }
+    }
+}
+
+/// One source of risk evidence. Returning `None` means "nothing unusual".
+/// Signals should swallow their own lookup failures: a broken signal must
+/// not lock everybody out.
+#[async_trait]
+pub trait RiskSignal: Send + Sync {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal>;
+}
+
+pub struct RiskAssessment {
+    pub score: u32,
+    pub decision: RiskDecision,
+    pub signals: Vec<Signal>,
+}
+
+#[derive(Clone)]
+pub struct RiskEngine {
+    signals: Vec<Arc<dyn RiskSignal>>,
+    challenge_score: u32,
+    block_score: u32,
+    /// How a [`RiskDecision::Challenge`] can be satisfied.
+    captcha: Captcha,
+}
+
+impl RiskEngine {
+    pub fn new(cfg: &SecurityConfig) -> Self {
+        Self {
+            signals: Vec::new(),
+            challenge_score: cfg.risk_challenge_score,
+            block_score: cfg.risk_block_score,
+            captcha: Captcha::from_env(),
+        }
+    }
+
+    /// The built-in signal set.
+    pub fn with_default_signals(cfg: &SecurityConfig) -> Self {
+        Self::new(cfg)
+            .with_signal(BannedUser)
+            .with_signal(IpReputation)
+            .with_signal(ReputationFeeds::new(cfg))
+            .with_signal(NewDevice::default())
+            .with_signal(NewCountry::default())
+            .with_signal(ImpossibleTravel::default())
+            .with_signal(FailureVelocity::default())
+            .with_signal(TimeOfDay::default())
+    }
+
+    pub fn with_signal(mut self, signal: impl RiskSignal + 'static) -> Self {
+        self.signals.push(Arc::new(signal));
+        self
+    }
+
+    pub fn captcha(&self) -> &Captcha {
+        &self.captcha
+    }
+
+    pub async fn assess(&self, db: &Db, ctx: &RiskContext<'_>) -> RiskAssessment {
+        let mut signals = Vec::new();
+        for signal in &self.signals {
+            if let Some(s) = signal.evaluate(db, ctx).await {
+                signals.push(s);
+            }
+        }
+        let (score, decision) = self.decide(signals.iter());
+        let assessment = RiskAssessment {
+            score,
+            decision,
+            signals,
+        };
+        record(db, ctx, &assessment).await;
+        assessment
+    }
+
+    /// The decision `assessment` would have come to without the signals
+    /// named in `ignored`.
+    pub fn decision_without(&self, assessment: &RiskAssessment, ignored: &[&str]) -> RiskDecision {
+        self.decide(
+            assessment
+                .signals
+                .iter()
+                .filter(|s| !ignored.contains(&s.name)),
+        )
+        .1
+    }
+
+    fn decide<'a>(&self, signals: impl Iterator<Item = &'a Signal> + Clone) -> (u32, RiskDecision) {
+        let score = signals.clone().map(|s| s.score).sum();
+        let reason = signals
+            .max_by_key(|s| s.score)
+            .map(|s| s.name)
+            .unwrap_or("risk");
+        let decision = if score >= self.block_score {
+            RiskDecision::Block(reason)
+        } else if score >= self.challenge_score {
+            RiskDecision::Challenge(reason)
+        } else {
+            RiskDecision::Allow
+        };
+        (score, decision)
+    }
+}
+
+async fn record(db: &Db, ctx: &RiskContext<'_>, assessment: &RiskAssessment) {
+    let res = sqlx::query(
+        "INSERT INTO risk_decisions (id, user_id, action, ip, user_agent, score, decision, signals, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())",
+    )
+    .bind(Uuid::new_v4())
+    .bind(ctx.user_id)
+    .bind(ctx.action)
+    .bind(ctx.ip)
+    .bind(ctx.user_agent)
+    .bind(assessment.score as i32)
+    .bind(assessment.decision.label())
+    .bind(serde_json::to_value(&assessment.signals).unwrap_or_default())
+    .execute(db)
+    .await;
+    if let Err(e) = res {
+        tracing::warn!("failed to record risk decision: {e}");
+    }
+}
+
+/// Name of the [`BannedUser`] signal.
+pub const USER_BANNED: &str = "user_banned";
+
+pub struct BannedUser;
+
+#[async_trait]
+impl RiskSignal for BannedUser {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let uid = ctx.user_id?;
+        sqlx::query("SELECT 1 FROM banned_users WHERE user_id = $1")
             .bind(uid)
             .fetch_optional(db)
             .await
-        {
-            return RiskDecision::Block("user_banned");
-        }
+            .ok()
+            .flatten()
+            .map(|_| Signal::new(USER_BANNED, DECISIVE, serde_json::json!({})))
     }
+}
 
-    // Banned IP
-    if let Some(ip) = ip {
-        if let Ok(Some(_)) = sqlx::query("SELECT 1 FROM banned_users WHERE ip = $1")
+/// Addresses, networks and ASNs we have banned outright: `ip_rules`, plus
+/// exact addresses on `banned_users`.
+pub struct IpReputation;
+
+#[async_trait]
+impl RiskSignal for IpReputation {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let ip = ctx.ip?;
+        if let Ok(addr) = ip.parse()
+            && let Some(rule) = ip_rules::matching(addr, ctx.geo.and_then(|g| g.asn))
+        {
+            return Some(Signal::new(
+                "ip_banned",
+                DECISIVE,
+                serde_json::json!({
+                    "rule_id": rule.id,
+                    "cidr": rule.cidr,
+                    "asn": rule.asn,
+                    "reason": rule.reason,
+                }),
+            ));
+        }
+        sqlx::query("SELECT 1 FROM banned_users WHERE ip = $1")
             .bind(ip)
             .fetch_optional(db)
             .await
-        {
-            return RiskDecision::Block("ip_banned");
+            .ok()
+            .flatten()
+            .map(|_| Signal::new("ip_banned", DECISIVE, serde_json::json!({})))
+    }
+}
+
+/// Categories the address is listed under in the local reputation feeds,
+/// each scored by its configured weight.
+pub struct ReputationFeeds {
+    pub weights: HashMap<String, u32>,
+    pub default_weight: u32,
+}
+
+impl ReputationFeeds {
+    pub fn new(cfg: &SecurityConfig) -> Self {
+        Self {
+            weights: cfg.reputation_weights.clone(),
+            default_weight: cfg.reputation_default_weight,
+        }
+    }
+}
+
+#[async_trait]
+impl RiskSignal for ReputationFeeds {
+    async fn evaluate(&self, _db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let tags = reputation::tags(ctx.ip?.parse().ok()?);
+        let score = tags
+            .iter()
+            .map(|t| {
+                self.weights
+                    .get(t.as_ref())
+                    .copied()
+                    .unwrap_or(self.default_weight)
+            })
+            .sum::<u32>();
+        (score > 0).then(|| {
+            Signal::new(
+                "ip_reputation",
+                score,
+                serde_json::json!({ "tags": tags.iter().map(|t| t.as_ref()).collect::<Vec<_>>() }),
+            )
+        })
+    }
+}
+
+pub struct NewDevice {
+    pub device_weight: u32,
+    pub network_weight: u32,
+}
+
+impl Default for NewDevice {
+    fn default() -> Self {
+        Self {
+            device_weight: 25,
+            network_weight: 15,
         }
     }
+}
 
-    // Brute force: recent failures
-    if let Some(uid) = user_id {
-        if let Ok(row) =
-            sqlx::query("SELECT failed_login_count, last_failed_at FROM users WHERE id = $1")
-                .bind(uid)
-                .fetch_optional(db)
-                .await
+#[async_trait]
+impl RiskSignal for NewDevice {
+    async fn evaluate(&self, _db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let device = ctx.device?;
+        let score = if device.new_device {
+            self.device_weight
+        } else {
+            0
+        } + if device.new_network {
+            self.network_weight
+        } else {
+            0
+        };
+        (score > 0).then(|| {
+            Signal::new(
+                "new_device",
+                score,
+                serde_json::json!({
+                    "new_device": device.new_device,
+                    "new_network": device.new_network,
+                }),
+            )
+        })
+    }
+}
+
+/// Recent credential failures against the account and from the address.
+/// Outright lockouts are [`lockout`](crate::security::lockout)'s job; this
+/// only raises the score of attempts that follow a run of failures.
+pub struct FailureVelocity {
+    pub window: Duration,
+    pub user_warn_after: i64,
+    pub ip_warn_after: i64,
+    pub weight: u32,
+}
+
+impl Default for FailureVelocity {
+    fn default() -> Self {
+        Self {
+            window: Duration::minutes(15),
+            user_warn_after: 3,
+            ip_warn_after: 10,
+            weight: 30,
+        }
+    }
+}
+
+#[async_trait]
+impl RiskSignal for FailureVelocity {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let since = OffsetDateTime::now_utc() - self.window;
+        let mut user_failures = 0;
+        if let Some(uid) = ctx.user_id
+            && let Ok(Some(r)) =
+                sqlx::query("SELECT failed_login_count, last_failed_at FROM users WHERE id = $1")
+                    .bind(uid)
+                    .fetch_optional(db)
+                    .await
         {
-            if let Some(r) = row {
-                let count: i64 = r.get("failed_login_count");
-                let last_failed: Option<OffsetDateTime> = r.get("last_failed_at");
-                if count >= 5 {
-                    if let Some(ts) = last_failed {
-                        if ts > OffsetDateTime::now_utc() - time::Duration::minutes(15) {
-                            return RiskDecision::Block("too_many_failures");
-                        }
-                    }
-                }
+            let last_failed: Option<OffsetDateTime> = r.get("last_failed_at");
+            if last_failed.is_some_and(|ts| ts > since) {
+                user_failures = r.get::<Option<i64>, _>("failed_login_count").unwrap_or(0);
             }
         }
+        let mut ip_failures = 0;
+        if let Some(ip) = ctx.ip {
+            ip_failures = sqlx::query_scalar(
+                "SELECT count(*) FROM login_logs
+                 WHERE ip = $1 AND NOT success AND failure_reason = ANY($2) AND created_at > $3",
+            )
+            .bind(ip)
+            .bind(stuffing::CREDENTIAL_FAILURES)
+            .bind(since)
+            .fetch_one(db)
+            .await
+            .unwrap_or(0);
+        }
+
+        let mut score = 0;
+        if user_failures >= self.user_warn_after {
+            score += self.weight;
+        }
+        if ip_failures >= self.ip_warn_after {
+            score += self.weight;
+        }
+        (score > 0).then(|| {
+            Signal::new(
+                "too_many_failures",
+                score,
+                serde_json::json!({ "user_failures": user_failures, "ip_failures": ip_failures }),
+            )
+        })
     }
+}
 
-    // Basic UA/IP heuristics placeholder
-    let _ = user_agent;
-    let _ = ip;
-    RiskDecision::Allow
+/// Sign-in at an hour of day (UTC) the user has never signed in at before.
+pub struct TimeOfDay {
+    /// Successful logins looked back over; fewer than `min_history` means we
+    /// do not know the user's habits yet.
+    pub history: i64,
+    pub min_history: usize,
+    pub weight: u32,
 }
 
-pub fn extract_ip(headers: &HeaderMap) -> Option<String> {
-    if let Some(forwarded) = headers.get("x-forwarded-for") {
-        if let Ok(val) = forwarded.to_str() {
-            return val.split(',').next().map(|s| s.trim().to_string());
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            history: 50,
+            min_history: 10,
+            weight: 10,
         }
     }
-    None
+}
+
+#[async_trait]
+impl RiskSignal for TimeOfDay {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let uid = ctx.user_id?;
+        let seen: Vec<OffsetDateTime> = sqlx::query_scalar(
+            "SELECT created_at FROM login_logs WHERE user_id = $1 AND success
+             ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(uid)
+        .bind(self.history)
+        .fetch_all(db)
+        .await
+        .ok()?;
+        if seen.len() < self.min_history {
+            return None;
+        }
+        let hour = OffsetDateTime::now_utc().hour() as i32;
+        // Allow an hour either side of anything seen before.
+        let familiar = seen.iter().any(|t| {
+            let diff = (t.hour() as i32 - hour).rem_euclid(24);
+            diff <= 1 || diff == 23
+        });
+        (!familiar).then(|| {
+            Signal::new(
+                "unusual_time",
+                self.weight,
+                serde_json::json!({ "hour_utc": hour }),
+            )
+        })
+    }
+}
+
+/// First sign-in from a country the user has never signed in from.
+pub struct NewCountry {
+    pub weight: u32,
+}
+
+impl Default for NewCountry {
+    fn default() -> Self {
+        Self { weight: 20 }
+    }
+}
+
+#[async_trait]
+impl RiskSignal for NewCountry {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let uid = ctx.user_id?;
+        let country = ctx.geo?.country.as_deref()?;
+        let (has_history, seen): (bool, bool) = sqlx::query_as(
+            "SELECT
+                 EXISTS (SELECT 1 FROM login_logs WHERE user_id = $1 AND success AND country IS NOT NULL),
+                 EXISTS (SELECT 1 FROM login_logs WHERE user_id = $1 AND success AND country = $2)",
+        )
+        .bind(uid)
+        .bind(country)
+        .fetch_one(db)
+        .await
+        .ok()?;
+        (has_history && !seen).then(|| {
+            Signal::new(
+                "new_country",
+                self.weight,
+                serde_json::json!({ "country": country }),
+            )
+        })
+    }
+}
+
+/// The distance from the previous successful sign-in could not have been
+/// covered in the time since.
+pub struct ImpossibleTravel {
+    /// Roughly airliner cruising speed.
+    pub max_speed_kmh: f64,
+    /// City-level GeoIP is often tens of kilometres off; shorter hops are noise.
+    pub min_distance_km: f64,
+    pub weight: u32,
+}
+
+impl Default for ImpossibleTravel {
+    fn default() -> Self {
+        Self {
+            max_speed_kmh: 1000.0,
+            min_distance_km: 300.0,
+            weight: 60,
+        }
+    }
+}
+
+#[async_trait]
+impl RiskSignal for ImpossibleTravel {
+    async fn evaluate(&self, db: &Db, ctx: &RiskContext<'_>) -> Option<Signal> {
+        let uid = ctx.user_id?;
+        let geo = ctx.geo?;
+        let (lat, lon) = (geo.latitude?, geo.longitude?);
+        let row = sqlx::query(
+            "SELECT latitude, longitude, country, created_at FROM login_logs
+             WHERE user_id = $1 AND success AND latitude IS NOT NULL AND longitude IS NOT NULL
+             ORDER BY created_at DESC LIMIT 1",
+        )
+        .bind(uid)
+        .fetch_optional(db)
+        .await
+        .ok()??;
+        let (prev_lat, prev_lon): (f64, f64) = (row.get("latitude"), row.get("longitude"));
+        let prev_at: OffsetDateTime = row.get("created_at");
+
+        let distance_km = haversine_km(prev_lat, prev_lon, lat, lon);
+        if distance_km < self.min_distance_km {
+            return None;
+        }
+        // Floor the elapsed time so back-to-back logins do not divide by ~0.
+        let hours =
+            ((OffsetDateTime::now_utc() - prev_at).as_seconds_f64() / 3600.0).max(1.0 / 60.0);
+        let speed_kmh = distance_km / hours;
+        (speed_kmh > self.max_speed_kmh).then(|| {
+            Signal::new(
+                "impossible_travel",
+                self.weight,
+                serde_json::json!({
+                    "from_country": row.get::<Option<String>, _>("country"),
+                    "to_country": geo.country,
+                    "distance_km": distance_km.round(),
+                    "hours": (hours * 10.0).round() / 10.0,
+                }),
+            )
+        })
+    }
+}
+
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
 }
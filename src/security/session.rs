@@ -0,0 +1,119 @@
+//! Login sessions (`sessions`): one row per sign-in, shared by every refresh
+//! token rotated from it or the BFF session backing it.
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, Row, postgres::PgRow};
+use uuid::Uuid;
+
+use crate::domain::session::Session;
+use crate::infra::db::Db;
+use crate::security::device::ClientInfo;
+
+impl FromRow<'_, PgRow> for Session {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            device_id: row.try_get("device_id")?,
+            user_agent: row.try_get("user_agent")?,
+            ip: row.try_get("ip")?,
+            country: row.try_get("country")?,
+            city: row.try_get("city")?,
+            asn: row.try_get("asn")?,
+            created_at: row.try_get("created_at")?,
+            last_seen_at: row.try_get("last_seen_at")?,
+            mfa_passed: row.try_get("mfa_passed")?,
+            suspicious: row.try_get("suspicious")?,
+        })
+    }
+}
+
+fn hash_token(raw: &str) -> String {
+    hex::encode(Sha256::digest(raw.as_bytes()))
+}
+
+pub async fn create(
+    db: &Db,
+    user_id: Uuid,
+    client: &ClientInfo,
+    mfa_passed: bool,
+    suspicious: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let geo = client.geo.clone().unwrap_or_default();
+    sqlx::query(
+        "INSERT INTO sessions (id, user_id, device_id, user_agent, ip, country, city, asn, latitude, longitude, created_at, last_seen_at, mfa_passed, suspicious)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now(), now(), $11, $12)",
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(client.device_hash())
+    .bind(&client.user_agent)
+    .bind(&client.ip)
+    .bind(geo.country)
+    .bind(geo.city)
+    .bind(geo.asn)
+    .bind(geo.latitude)
+    .bind(geo.longitude)
+    .bind(mfa_passed)
+    .bind(suspicious)
+    .execute(db)
+    .await?;
+    Ok(id)
+}
+
+pub async fn touch(db: &Db, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET last_seen_at = now() WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Arms the "this wasn't me" link for a session and returns its raw token.
+pub async fn issue_report_token(db: &Db, id: Uuid) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let raw = hex::encode(bytes);
+    sqlx::query("UPDATE sessions SET report_token_hash = $1 WHERE id = $2")
+        .bind(hash_token(&raw))
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(raw)
+}
+
+/// Redeems a "this wasn't me" token, returning the reported session. Each
+/// token works once.
+pub async fn take_reported(db: &Db, raw: &str) -> Result<Option<Session>, sqlx::Error> {
+    sqlx::query_as(
+        "UPDATE sessions SET report_token_hash = NULL, reported_at = now()
+         WHERE report_token_hash = $1
+         RETURNING id, user_id, device_id, user_agent, ip, country, city, asn, created_at, last_seen_at, mfa_passed, suspicious",
+    )
+    .bind(hash_token(raw))
+    .fetch_optional(db)
+    .await
+}
+
+/// Revokes every session of a user along with the refresh tokens and BFF
+/// sessions hanging off them.
+pub async fn revoke_all_for_user(db: &Db, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(db)
+    .await?;
+    sqlx::query("DELETE FROM bff_sessions WHERE user_id = $1")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
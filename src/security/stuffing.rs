@@ -0,0 +1,175 @@
+//! Credential-stuffing detection.
+//!
+//! Per-account lockouts do nothing against one password sprayed across
+//! thousands of accounts, so wrong credentials are also counted per source:
+//! the client address and its /24 (IPv4) or /48 (IPv6) network.
+//! `login_logs` carries a keyed hash of the identifier each attempt used,
+//! which lets us count how many distinct accounts a source has been
+//! guessing at, including ones that do not exist. Sources over the soft
+//! limits must pass a challenge before their credentials are even checked;
+//! sources over the hard limits get a temporary `ip_rules` ban that admins
+//! can see and lift.
+
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::Row;
+use time::{Duration, OffsetDateTime};
+
+use crate::infra::db::Db;
+use crate::security::device::{ClientInfo, network_of};
+use crate::security::ip_rules::{self, IpRuleError, NewIpRule};
+use crate::security::risk::RiskDecision;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const WINDOW: Duration = Duration::minutes(15);
+const BLOCK_FOR: Duration = Duration::hours(1);
+
+/// Failures from one address before it is challenged, whatever the accounts.
+const IP_CHALLENGE_FAILURES: i64 = 20;
+/// Distinct accounts tried from one address.
+const IP_CHALLENGE_ACCOUNTS: i64 = 5;
+const IP_BLOCK_ACCOUNTS: i64 = 20;
+/// Distinct accounts tried from one network. Higher than the per-address
+/// limits: offices and carrier NAT put many honest users behind one range.
+const SUBNET_CHALLENGE_ACCOUNTS: i64 = 15;
+const SUBNET_CHALLENGE_FAILURES: i64 = 100;
+const SUBNET_BLOCK_ACCOUNTS: i64 = 50;
+
+/// Failure reasons that count as guesses: wrong credentials, not refusals we
+/// issued ourselves. Counting refusals would keep a challenged or blocked
+/// source over the limits for as long as it keeps retrying.
+pub const CREDENTIAL_FAILURES: &[&str] = &["unknown_user", "invalid_password", "invalid_mfa_code"];
+
+/// Failed sign-ins from a source within the window.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SourceStats {
+    pub ip_failures: i64,
+    pub ip_accounts: i64,
+    pub subnet_failures: i64,
+    pub subnet_accounts: i64,
+}
+
+/// Stable key for the identifier a sign-in used, so the emails tried against
+/// accounts that do not exist are never stored. Keyed with
+/// `SecurityConfig::identifier_hash_key`: a plain digest could be reversed by
+/// hashing any list of known emails.
+pub fn identifier_hash(key: &[u8], identifier: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(identifier.trim().to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub async fn stats(db: &Db, ip: &str) -> Result<SourceStats, sqlx::Error> {
+    let Some(network) = network_of(ip) else {
+        return Ok(SourceStats::default());
+    };
+    let row = sqlx::query(
+        "SELECT count(*) FILTER (WHERE ip = $1) AS ip_failures,
+                count(DISTINCT identifier_hash) FILTER (WHERE ip = $1) AS ip_accounts,
+                count(*) AS subnet_failures,
+                count(DISTINCT identifier_hash) AS subnet_accounts
+         FROM login_logs
+         WHERE network = $2 AND NOT success AND failure_reason = ANY($3) AND created_at > $4",
+    )
+    .bind(ip)
+    .bind(network)
+    .bind(CREDENTIAL_FAILURES)
+    .bind(OffsetDateTime::now_utc() - WINDOW)
+    .fetch_one(db)
+    .await?;
+    Ok(SourceStats {
+        ip_failures: row.get("ip_failures"),
+        ip_accounts: row.get("ip_accounts"),
+        subnet_failures: row.get("subnet_failures"),
+        subnet_accounts: row.get("subnet_accounts"),
+    })
+}
+
+/// Decides whether a sign-in from this client may be checked at all. Runs
+/// before the account lookup so a banned or spraying source learns nothing
+/// about the credentials it sends.
+pub async fn gate(db: &Db, client: &ClientInfo) -> RiskDecision {
+    let Some(ip) = client.ip.as_deref() else {
+        return RiskDecision::Allow;
+    };
+    if let Ok(addr) = ip.parse::<IpAddr>()
+        && ip_rules::matching(addr, client.geo.as_ref().and_then(|g| g.asn)).is_some()
+    {
+        return RiskDecision::Block("ip_banned");
+    }
+    let stats = match stats(db, ip).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            tracing::warn!("credential stuffing check failed: {e}");
+            return RiskDecision::Allow;
+        }
+    };
+    if stats.ip_accounts >= IP_BLOCK_ACCOUNTS || stats.subnet_accounts >= SUBNET_BLOCK_ACCOUNTS {
+        RiskDecision::Block("credential_stuffing")
+    } else if stats.ip_accounts >= IP_CHALLENGE_ACCOUNTS
+        || stats.ip_failures >= IP_CHALLENGE_FAILURES
+        || stats.subnet_accounts >= SUBNET_CHALLENGE_ACCOUNTS
+        || stats.subnet_failures >= SUBNET_CHALLENGE_FAILURES
+    {
+        RiskDecision::Challenge("credential_stuffing")
+    } else {
+        RiskDecision::Allow
+    }
+}
+
+/// Called after each credential failure: once a source crosses a hard limit it is
+/// banned for a while, so the block holds across instances and shows up in
+/// the admin rule list.
+pub async fn observe_failure(db: &Db, client: &ClientInfo) {
+    let Some(ip) = client.ip.as_deref() else {
+        return;
+    };
+    let stats = match stats(db, ip).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            tracing::warn!("credential stuffing check failed: {e}");
+            return;
+        }
+    };
+    let target = if stats.subnet_accounts >= SUBNET_BLOCK_ACCOUNTS {
+        network_of(ip).map(|net| (net, stats.subnet_accounts))
+    } else if stats.ip_accounts >= IP_BLOCK_ACCOUNTS {
+        Some((ip.to_string(), stats.ip_accounts))
+    } else {
+        None
+    };
+    let Some((cidr, accounts)) = target else {
+        return;
+    };
+    if let Ok(addr) = ip.parse::<IpAddr>()
+        && ip_rules::matching(addr, None).is_some()
+    {
+        return;
+    }
+    if let Err(e) = ban(db, &cidr, accounts).await {
+        tracing::error!("failed to ban credential stuffing source {cidr}: {e}");
+    }
+}
+
+async fn ban(db: &Db, cidr: &str, accounts: i64) -> Result<(), IpRuleError> {
+    let rule = ip_rules::create(
+        db,
+        NewIpRule {
+            cidr: Some(cidr.to_string()),
+            asn: None,
+            reason: format!(
+                "credential stuffing: {accounts} accounts tried within {} minutes",
+                WINDOW.whole_minutes()
+            ),
+            expires_at: Some(OffsetDateTime::now_utc() + BLOCK_FOR),
+            created_by: None,
+        },
+    )
+    .await?;
+    tracing::warn!(rule = %rule.id, "banned {cidr} for credential stuffing");
+    ip_rules::reload(db).await?;
+    Ok(())
+}
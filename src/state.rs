@@ -1,9 +1,13 @@
 use std::sync::Arc;
 
 use crate::infra::db::Db;
+use crate::infra::geoip::GeoIp;
+use crate::infra::mailer::Mailer;
 use crate::infra::supabase::SupabaseCtx;
 use crate::security::config::SecurityConfig;
+use crate::security::email_policy::EmailPolicy;
 use crate::security::jwt::JwtManager;
+use crate::security::risk::RiskEngine;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -11,6 +15,10 @@ pub struct AppState {
     pub jwt: JwtManager,
     pub security: SecurityConfig,
     pub supabase: SupabaseCtx,
+    pub mailer: Mailer,
+    pub risk: RiskEngine,
+    pub geoip: GeoIp,
+    pub email_policy: EmailPolicy,
 }
 
 impl AppState {
@@ -19,12 +27,20 @@ impl AppState {
         jwt: JwtManager,
         security: SecurityConfig,
         supabase: SupabaseCtx,
+        mailer: Mailer,
+        risk: RiskEngine,
+        geoip: GeoIp,
     ) -> Arc<Self> {
+        let email_policy = EmailPolicy::from_config(&security);
         Arc::new(Self {
             db,
             jwt,
             security,
             supabase,
+            mailer,
+            risk,
+            geoip,
+            email_policy,
         })
     }
 }